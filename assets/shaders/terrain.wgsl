@@ -0,0 +1,123 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::pbr_ambient
+#import bevy_pbr::shadows
+#import bevy_pbr::fog
+#import bevy_pbr::pbr_functions
+
+// see TerrainParams in material.rs
+struct TerrainParams {
+    snow_height: f32,
+    grass_height: f32,
+    sand_height: f32,
+    stone_slope: f32,
+    height_blend: f32,
+    slope_blend: f32,
+    tile_size: f32,
+    voxel_scale: f32,
+    triplanar: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> params: TerrainParams;
+@group(1) @binding(1)
+var layers: texture_2d_array<f32>;
+@group(1) @binding(2)
+var layers_sampler: sampler;
+
+// layers, in the order of LAYERS in material.rs
+const GRASS: i32 = 0;
+const WATER: i32 = 1;
+const SAND: i32 = 2;
+const SNOW: i32 = 3;
+const STONE: i32 = 4;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+// where and how much to sample along each axis
+struct Projection {
+    x: vec2<f32>,
+    y: vec2<f32>,
+    z: vec2<f32>,
+    weights: vec3<f32>,
+};
+
+fn project(p: vec3<f32>, n: vec3<f32>) -> Projection {
+    var out: Projection;
+    let uvw = p / params.tile_size;
+    out.x = uvw.zy;
+    out.y = uvw.xz;
+    out.z = uvw.xy;
+    if (params.triplanar == 0u) {
+        out.weights = vec3<f32>(0.0, 1.0, 0.0);
+        return out;
+    }
+    // sharpen the blend so only the steep faces use the side projections
+    let w = pow(abs(n), vec3<f32>(4.0));
+    out.weights = w / (w.x + w.y + w.z);
+    return out;
+}
+
+fn tile(p: Projection, layer: i32) -> vec4<f32> {
+    // sample every axis even when the weight is zero, textureSample needs uniform control flow
+    return textureSample(layers, layers_sampler, p.x, layer) * p.weights.x
+        + textureSample(layers, layers_sampler, p.y, layer) * p.weights.y
+        + textureSample(layers, layers_sampler, p.z, layer) * p.weights.z;
+}
+
+// blend 0.0 to 1.0 arround edge
+fn band(edge: f32, width: f32, x: f32) -> f32 {
+    return smoothstep(edge - width, edge + width, x);
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let n = normalize(in.world_normal);
+    // the same units genslope and genchunk use
+    let h = in.world_position.y / params.voxel_scale;
+    let slope = sqrt(max(1.0 - n.y * n.y, 0.0)) / max(n.y, 0.001);
+
+    let uv = project(in.world_position.xyz, n);
+
+    let land = mix(tile(uv, GRASS), tile(uv, STONE), band(params.stone_slope, params.slope_blend, slope));
+    let high = mix(land, tile(uv, SNOW), band(params.snow_height, params.height_blend, h));
+    let shore = mix(tile(uv, SAND), high, band(params.grass_height, params.height_blend, h));
+    var base_color = mix(tile(uv, WATER), shore, band(params.sand_height, params.height_blend, h));
+#ifdef VERTEX_COLORS
+    // roads, a darker gravel made from the sand tile
+    let gravel = tile(uv, SAND) * vec4<f32>(0.55, 0.5, 0.45, 1.0);
+    base_color = mix(base_color, gravel, in.color.r);
+#endif
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = vec4<f32>(base_color.rgb, 1.0);
+    pbr_input.material.perceptual_roughness = 0.9;
+    pbr_input.material.metallic = 0.0;
+
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh.flags;
+
+    var output_color = pbr(pbr_input);
+
+    if (fog.mode != FOG_MODE_OFF) {
+        output_color = apply_fog(output_color, in.world_position.xyz, view.world_position.xyz);
+    }
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+    return output_color;
+}
@@ -0,0 +1,88 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::pbr_ambient
+#import bevy_pbr::shadows
+#import bevy_pbr::fog
+#import bevy_pbr::pbr_functions
+
+// see WaterParams in water.rs
+struct WaterParams {
+    shallow_color: vec4<f32>,
+    deep_color: vec4<f32>,
+    foam_color: vec4<f32>,
+    deep: f32,
+    foam_depth: f32,
+    wave_scale: f32,
+    wave_speed: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> params: WaterParams;
+@group(1) @binding(1)
+var waves: texture_2d<f32>;
+@group(1) @binding(2)
+var waves_sampler: sampler;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+fn wave(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(waves, waves_sampler, uv).xyz * 2.0 - 1.0;
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    // two copies of the normal map scrolling in different directions, so the pattern does not repeat visibly
+    let t = globals.time * params.wave_speed;
+    let uv = in.world_position.xz / params.wave_scale;
+    let a = wave(uv + vec2<f32>(t, t * 0.3));
+    let b = wave(uv * 0.7 + vec2<f32>(-t * 0.4, t * 0.8));
+    let n = normalize(a + b);
+
+#ifdef VERTEX_UVS
+    // the depth of the terrain under this point, negative over land
+    let depth = in.uv.x;
+#else
+    let depth = params.deep;
+#endif
+
+    var color = mix(params.shallow_color, params.deep_color, clamp(depth / params.deep, 0.0, 1.0));
+    // the waves move the edge of the foam arround a bit
+    let foam = 1.0 - smoothstep(0.0, params.foam_depth, depth + a.x * params.foam_depth * 0.5);
+    color = mix(color, params.foam_color, foam);
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = color;
+    pbr_input.material.perceptual_roughness = mix(0.08, 0.8, foam);
+    pbr_input.material.metallic = 0.0;
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_ALPHA_MODE_BLEND;
+
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(n, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh.flags;
+
+    var output_color = pbr(pbr_input);
+
+    if (fog.mode != FOG_MODE_OFF) {
+        output_color = apply_fog(output_color, in.world_position.xyz, view.world_position.xyz);
+    }
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+#ifdef PREMULTIPLY_ALPHA
+    output_color = premultiply_alpha(pbr_input.material.flags, output_color);
+#endif
+    return output_color;
+}
@@ -0,0 +1,50 @@
+//! how long each step of making a chunk takes, to catch the generator getting slower
+//! `cargo bench --bench chunks`, criterion keeps the last run in target/criterion and reports changes against it
+
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use bevy::render::texture::{CompressedImageFormats, ImageType};
+use criterion::{criterion_group, criterion_main, Criterion};
+use terrain::map;
+
+/// away from the origin, so the chunk has hills and sea like most do
+const SEED: (f32, f32) = (3.0, -2.0);
+
+fn tile(name: &str) -> Image {
+    let bytes = std::fs::read(std::path::Path::new("assets").join(name)).unwrap();
+    Image::from_buffer(&bytes, ImageType::Extension("png"), CompressedImageFormats::NONE, true).unwrap()
+}
+
+/// map::gen takes the tiles from Assets, which only an app can make
+fn app_with_tiles() -> App {
+    let mut app = App::new();
+    app.add_plugin(AssetPlugin::default()).add_asset::<Image>();
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    for name in [map::ASSETS_GRASS, map::ASSETS_WATER, map::ASSETS_SAND, map::ASSETS_SNOW, map::ASSETS_STONE].iter() {
+        images.set_untracked(*name, tile(name));
+    }
+    app
+}
+
+fn chunks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunks");
+    let seed = map::world_chunk(SEED, 0);
+    let regs = map::genchunkregs(seed);
+    let hightmap = map::genchunk(seed, &regs);
+    let slope = map::genslope(&hightmap);
+    let (grass, water, sand, snow, stone) =
+        (tile(map::ASSETS_GRASS), tile(map::ASSETS_WATER), tile(map::ASSETS_SAND), tile(map::ASSETS_SNOW), tile(map::ASSETS_STONE));
+    group.bench_function("genchunk", |b| b.iter(|| map::genchunk(seed, &regs)));
+    group.bench_function("genslope", |b| b.iter(|| map::genslope(&hightmap)));
+    group.bench_function("chunktomesh", |b| b.iter(|| map::chunktomesh(&hightmap)));
+    group.bench_function("chunktotexture", |b| {
+        b.iter(|| map::chunktotexture(&hightmap, &slope, &regs, &grass, &water, &sand, &snow, &stone, seed))
+    });
+    let mut app = app_with_tiles();
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    group.bench_function("gen", |b| b.iter(|| map::gen(&mut images, SEED, 0).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, chunks);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! chunks per second from the scalar and batch hightmap paths
+//! `cargo bench --bench hightmaps`
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use terrain::map;
+
+/// enough chunks to keep every core busy
+const CHUNKS: i32 = 8;
+
+fn seeds() -> Vec<(f32, f32)> {
+    (0..CHUNKS).flat_map(|x| (0..CHUNKS).map(move |z| (x as f32, z as f32))).collect()
+}
+
+fn hightmaps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hightmaps");
+    let seed = map::world_chunk((3.0, -2.0), 0);
+    let regs = map::genchunkregs(seed);
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("genchunk", |b| b.iter(|| map::genchunk(seed, &regs)));
+    group.bench_function("genchunk_batch", |b| b.iter(|| map::genchunk_batch(seed, &regs)));
+
+    let seeds = seeds();
+    group.throughput(Throughput::Elements(seeds.len() as u64));
+    group.bench_function("genhightmap each", |b| {
+        b.iter_batched(|| seeds.clone(), |s| s.iter().map(|s| map::genhightmap(*s, 0)).collect::<Vec<_>>(), BatchSize::SmallInput)
+    });
+    group.bench_function("genhightmaps parallel", |b| b.iter(|| map::genhightmaps(&seeds, 0)));
+    group.finish();
+}
+
+criterion_group!(benches, hightmaps);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+//! balls dropped in front of the camera roll down the hills
+//! run with `cargo run --example rolling_hills --features physics`, fly arround with the normal controls
+
+use bevy::prelude::*;
+use bevy::render::mesh::shape;
+use bevy_rapier3d::prelude::*;
+use terrain::map::{HeightCache, VOXEL_SCALE};
+use terrain::chunk::WorldSeed;
+use terrain::physics::HasCollider;
+use terrain::TerrainPlugin;
+
+/// seconds between balls
+const DROP_EVERY: f32 = 0.5;
+/// how far in front of the camera balls are dropped, in world units
+const DROP_AHEAD: f32 = 8.0;
+/// balls below this are gone for good
+const LOST: f32 = -50.0;
+
+#[derive(Component)]
+struct Ball;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugin(TerrainPlugin)
+        .add_system(drop_balls)
+        .add_system(remove_lost)
+        .run();
+}
+
+/// drop a ball a little above the ground in front of the camera, once the ground has colliders
+#[allow(clippy::too_many_arguments)]
+fn drop_balls(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut since: Local<f32>,
+    time: Res<Time>,
+    world: Res<WorldSeed>,
+    cameras: Query<&Transform, With<Camera>>,
+    ground: Query<(), With<HasCollider>>,
+) {
+    *since += time.delta_seconds();
+    if *since < DROP_EVERY || ground.is_empty() {
+        return;
+    }
+    *since = 0.0;
+    let camera = match cameras.iter().next() {
+        Some(c) => c,
+        None => return
+    };
+    let ahead = camera.forward() * Vec3::new(1.0, 0.0, 1.0);
+    let p = camera.translation + ahead.normalize_or_zero() * DROP_AHEAD;
+    let h = HeightCache::new(world.0).get(((p.x / VOXEL_SCALE).round() as i32, (p.z / VOXEL_SCALE).round() as i32));
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(shape::UVSphere {radius: 0.3, ..Default::default()}.into()),
+            material: materials.add(Color::rgb(0.8, 0.2, 0.2).into()),
+            transform: Transform::from_xyz(p.x, h * VOXEL_SCALE + 3.0, p.z),
+            ..Default::default()
+        },
+        RigidBody::Dynamic,
+        Collider::ball(0.3),
+        Restitution::coefficient(0.3),
+        Friction::coefficient(0.8),
+        Ball,
+    ));
+}
+
+/// despawn balls that fell off the loaded chunks
+fn remove_lost(
+    mut commands: Commands,
+    balls: Query<(Entity, &Transform), With<Ball>>,
+) {
+    for (e, t) in balls.iter() {
+        if t.translation.y < LOST {
+            commands.entity(e).despawn();
+        }
+    }
+}
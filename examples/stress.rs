@@ -0,0 +1,151 @@
+//! fly a scripted camera path through the loader without a window, and report how it kept up
+//! `cargo run --release --example stress -- --laps 2 --view 3`
+//! the camera circles the origin at a steady speed, the second lap comes back to chunks that are in the packed cache
+//! frame times are how long each update took, nothing is rendered so this is the cost of loading and generating alone
+
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use bevy::render::texture::ImagePlugin;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use terrain::chunk::{self, WorldSeed};
+use terrain::heightmap::Imported;
+use terrain::loader::{self, ViewDistance};
+use terrain::material::{self, TerrainAssets, TerrainMaterial};
+use terrain::packed::Cache;
+use terrain::poi::Pois;
+use terrain::road::{RoadChanged, Roads};
+use terrain::water::{self, WaterMaterial};
+use terrain::map;
+
+const USAGE: &str = "usage: stress [--laps n] [--view n] [--seed n] [--radius chunks] [--speed chunks-per-second]";
+
+/// the frame the path is scripted for, the loader does not look at time
+const FRAME: f32 = 1.0 / 60.0;
+/// updates to wait for the tiles before giving up, they load from assets/
+const LOAD_FRAMES: usize = 1000;
+
+struct Options {
+    laps: u32,
+    view: i32,
+    seed: u32,
+    /// of the circle flown, in chunks
+    radius: f32,
+    /// along the circle, in chunks per second of scripted time
+    speed: f32,
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut o = Options {laps: 1, view: ViewDistance::default().0, seed: 0, radius: 8.0, speed: 2.0};
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", flag))?;
+        let bad = || format!("{} needs a number", flag);
+        match flag.as_str() {
+            "--laps" => o.laps = value.parse().map_err(|_| bad())?,
+            "--view" => o.view = value.parse().map_err(|_| bad())?,
+            "--seed" => o.seed = value.parse().map_err(|_| bad())?,
+            "--radius" => o.radius = value.parse().map_err(|_| bad())?,
+            "--speed" => o.speed = value.parse().map_err(|_| bad())?,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    if o.laps == 0 || o.radius <= 0.0 || o.speed <= 0.0 {
+        return Err("--laps, --radius and --speed must be over 0".to_string());
+    }
+    Ok(o)
+}
+
+/// the loader, generator and the assets they need, but no window, input or rendering
+fn app(o: &Options) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(ImagePlugin::default())
+        .add_asset::<Mesh>()
+        .add_asset::<TerrainMaterial>()
+        .add_asset::<WaterMaterial>()
+        .insert_resource(WorldSeed(o.seed))
+        .insert_resource(ViewDistance(o.view))
+        .init_resource::<Imported>()
+        .init_resource::<Cache>()
+        .insert_resource(Roads::default())
+        .insert_resource(Pois::default())
+        .add_event::<RoadChanged>()
+        .add_startup_system(loader::init)
+        .add_startup_system(material::init)
+        .add_startup_system(water::init)
+        .add_system(loader::load)
+        .add_system(loader::unload)
+        .add_system(material::build)
+        .add_system(chunk::generate_maps.after(material::build));
+    app.world.spawn((Camera::default(), Transform::default()));
+    app
+}
+
+/// where the camera is after t seconds, looking along the circle
+fn eye(o: &Options, t: f32) -> Transform {
+    let r = o.radius * map::getchunksize();
+    let a = t * o.speed / o.radius;
+    let p = Vec3::new(r * a.cos(), 20.0, r * a.sin());
+    Transform::from_translation(p).looking_at(p + Vec3::new(-a.sin(), 0.0, a.cos()), Vec3::Y)
+}
+
+/// the most memory the process has had resident, in KB, only known on linux
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn run(o: &Options) -> Result<(), String> {
+    let mut app = app(o);
+    let mut waited = 0;
+    while app.world.get_resource::<TerrainAssets>().is_none_or(|a| a.material.is_none()) {
+        if waited == LOAD_FRAMES {
+            return Err("the terrain tiles did not load, use cargo run or set BEVY_ASSET_ROOT to the repository so assets/ is found".to_string());
+        }
+        app.update();
+        std::thread::sleep(Duration::from_millis(1));
+        waited += 1;
+    }
+
+    let frames = (o.laps as f32 * std::f32::consts::TAU * o.radius / o.speed / FRAME).ceil() as usize;
+    let mut times = Vec::with_capacity(frames);
+    let mut generated = HashSet::new();
+    let mut renders = app.world.query_filtered::<Entity, With<Handle<TerrainMaterial>>>();
+    let mut cameras = app.world.query_filtered::<&mut Transform, With<Camera>>();
+    let start = Instant::now();
+    for frame in 0..frames {
+        *cameras.single_mut(&mut app.world) = eye(o, frame as f32 * FRAME);
+        let before = Instant::now();
+        app.update();
+        times.push(before.elapsed());
+        generated.extend(renders.iter(&app.world));
+    }
+    let total = start.elapsed();
+
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / frames.max(1) as u32;
+    let percentile = |p: f32| times[((frames as f32 * p) as usize).min(frames - 1)];
+    println!("{} frames, {} laps of {} chunks radius at {} chunks/s, view distance {}", frames, o.laps, o.radius, o.speed, o.view);
+    println!("frame ms: mean {:.2} p50 {:.2} p99 {:.2} max {:.2}", ms(mean), ms(percentile(0.5)), ms(percentile(0.99)), ms(times[frames - 1]));
+    println!("{} chunks generated, {:.1} chunks/s", generated.len(), generated.len() as f64 / total.as_secs_f64());
+    match peak_memory() {
+        Some(kb) => println!("peak memory {:.1} MB", kb as f64 / 1024.0),
+        None => println!("peak memory unknown"),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = parse(&args).and_then(|o| run(&o)) {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,334 @@
+//! generate a range of chunks without opening a window, and write them to disk
+//! `terrain-gen --seed 3 --from -2,-2 --to 2,2 --out out` writes every output for 25 chunks
+//! the stitched outputs join the whole range into one heightmap or mesh, `--import` generates chunks from one instead of the procedural hights
+//! the output only depends on the seed and the range, not on the number of threads, so it can be diffed between versions
+//! `--batch true` generates the hights with the faster batch path, which is close to but not exactly the game's
+//! `terrain-gen serve --addr 127.0.0.1:8080` answers http requests for chunks instead of writing files, see terrain::serve
+//! `terrain-gen host --addr 127.0.0.1:7878` is the authoritative server for games started with `--connect`, see terrain::net
+
+use bevy::prelude::{Image, Mesh};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use terrain::dem::{self, DemOptions};
+use terrain::export::{self, Chunk, Tiles};
+use terrain::heightmap::{Format, Heightmap};
+use terrain::map::{self, ChunkData};
+use terrain::poi::Pois;
+use terrain::pyramid::{self, Pyramid};
+use terrain::net;
+use terrain::packed::Packed;
+use terrain::serve::Server;
+
+/// chunks generated together by --batch, per thread
+const BATCH_CHUNKS: usize = 16;
+
+const USAGE: &str = "usage: terrain-gen [serve|host] [--addr host:port] [--cache n] [--seed n] [--from x,z] [--to x,z] [--threads n] [--batch true|false] [--out dir] [--outputs height,slope,material,mesh,stitched,stitched-mesh,tiles,packed] [--height-format f32|u16|png16] [--import heightmap|dem.asc|dem.hgt] [--dem-scale metres] [--dem-exaggeration n] [--dem-detail n] [--dem-units metres|degrees] [--mesh-format obj|glb|stl] [--base n] [--assets dir]";
+
+/// things that can be written for each chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Height,
+    Slope,
+    Material,
+    Mesh,
+    /// one heightmap for the whole range
+    Stitched,
+    /// one mesh for the whole range
+    StitchedMesh,
+    /// a slippy map tile pyramid of the whole range, in tiles/
+    Tiles,
+    /// the hights quantized and compressed, see terrain::packed
+    Packed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MeshFormat {
+    Obj,
+    /// binary gltf with the chunk texture
+    Glb,
+    /// binary stl with walls and a bottom, caves are left out
+    Stl,
+}
+
+impl MeshFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "obj",
+            MeshFormat::Glb => "glb",
+            MeshFormat::Stl => "stl",
+        }
+    }
+}
+
+struct Options {
+    seed: u32,
+    /// inclusive chunk range
+    from: (i32, i32),
+    to: (i32, i32),
+    threads: usize,
+    /// generate hights with map::genhightmaps, faster but only within map::BATCH_TOLERANCE of the game's
+    batch: bool,
+    out: PathBuf,
+    assets: PathBuf,
+    outputs: Vec<Output>,
+    height_format: Format,
+    mesh_format: MeshFormat,
+    /// how far the bottom of stl files is below the lowest point, in points
+    base: f32,
+    import: Option<Heightmap>,
+    /// where serve listens
+    addr: String,
+    /// how many responses serve keeps
+    cache: usize,
+}
+
+/// parse "x,z"
+fn pair(s: &str) -> Result<(i32, i32), String> {
+    let mut parts = s.split(',').map(|p| p.trim().parse::<i32>());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(x)), Some(Ok(z)), None) => Ok((x, z)),
+        _ => Err(format!("expected x,z but got {}", s)),
+    }
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut o = Options {
+        seed: 0,
+        from: (0, 0),
+        to: (0, 0),
+        threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        batch: false,
+        out: PathBuf::from("out"),
+        assets: PathBuf::from("assets"),
+        outputs: vec![Output::Height, Output::Slope, Output::Material, Output::Mesh],
+        height_format: Format::F32,
+        mesh_format: MeshFormat::Obj,
+        base: 2.0,
+        import: None,
+        addr: "127.0.0.1:8080".to_string(),
+        cache: 1024,
+    };
+    let mut import = None;
+    let mut dem = DemOptions::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{} needs a value", flag))?;
+        let number = || value.parse::<u32>().map_err(|_| format!("{} needs a number", flag));
+        match flag.as_str() {
+            "--seed" => o.seed = number()?,
+            "--from" => o.from = pair(value)?,
+            "--to" => o.to = pair(value)?,
+            "--threads" => o.threads = (number()? as usize).max(1),
+            "--batch" => o.batch = value.parse().map_err(|_| format!("{} needs true or false", flag))?,
+            "--addr" => o.addr = value.clone(),
+            "--cache" => o.cache = number()? as usize,
+            "--out" => o.out = PathBuf::from(value),
+            "--assets" => o.assets = PathBuf::from(value),
+            "--base" => o.base = value.parse().map_err(|_| format!("{} needs a number", flag))?,
+            "--import" => import = Some(PathBuf::from(value)),
+            "--dem-scale" => dem.metres_per_point = Some(value.parse().map_err(|_| format!("{} needs a number", flag))?),
+            "--dem-exaggeration" => dem.exaggeration = value.parse().map_err(|_| format!("{} needs a number", flag))?,
+            "--dem-detail" => dem.detail = value.parse().map_err(|_| format!("{} needs a number", flag))?,
+            "--dem-units" => dem.units = Some(value.parse()?),
+            "--outputs" => o.outputs = value.split(',').map(|v| match v {
+                "height" => Ok(Output::Height),
+                "slope" => Ok(Output::Slope),
+                "material" => Ok(Output::Material),
+                "mesh" => Ok(Output::Mesh),
+                "stitched" => Ok(Output::Stitched),
+                "stitched-mesh" => Ok(Output::StitchedMesh),
+                "tiles" => Ok(Output::Tiles),
+                "packed" => Ok(Output::Packed),
+                _ => Err(format!("unknown output {}", v)),
+            }).collect::<Result<_, _>>()?,
+            "--height-format" => o.height_format = match value.as_str() {
+                "f32" => Format::F32,
+                "u16" => Format::U16,
+                "png16" => Format::Png16,
+                _ => return Err(format!("unknown height format {}", value)),
+            },
+            "--mesh-format" => o.mesh_format = match value.as_str() {
+                "obj" => MeshFormat::Obj,
+                "glb" => MeshFormat::Glb,
+                "stl" => MeshFormat::Stl,
+                _ => return Err(format!("unknown mesh format {}", value)),
+            },
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    if o.from.0 > o.to.0 || o.from.1 > o.to.1 {
+        return Err("--from must not be past --to".to_string());
+    }
+    // read after all the flags, the dem options can come after --import
+    o.import = import.map(|p| dem::import(&p, &dem)).transpose()?;
+    Ok(o)
+}
+
+/// write a mesh in the chosen format, the texture is only used by glb
+fn write_mesh(o: &Options, path: &Path, mesh: &Mesh, heightmap: &Heightmap, texture: impl FnOnce() -> Image) -> Result<(), String> {
+    match o.mesh_format {
+        MeshFormat::Obj => export::write_file(path, |out| export::write_obj(out, mesh)),
+        MeshFormat::Glb => export::write_file(path, |out| export::write_glb(out, mesh, Some(texture()))),
+        MeshFormat::Stl => {
+            let lo = heightmap.data.iter().cloned().fold(f32::MAX, f32::min);
+            export::write_file(path, |out| export::write_stl(out, heightmap, lo - o.base))
+        }
+    }
+}
+
+/// what the stitched outputs need from a chunk, kept from the per chunk pass so nothing is generated twice
+struct Kept {
+    hightmap: ChunkData<f32>,
+    /// only for a glb stitched mesh
+    texture: Option<Image>,
+}
+
+/// what one thread kept, or why it stopped
+type Worker = Result<Vec<((i32, i32), Kept)>, String>;
+
+/// the procedural hights of a block of chunks generated together, empty without --batch
+/// chunks covered by --import are left out, they do not use them
+fn batch_bases(o: &Options, block: &[(i32, i32)]) -> HashMap<(i32, i32), ChunkData<f32>> {
+    if !o.batch {
+        return HashMap::new();
+    }
+    let seeds: Vec<(i32, i32)> = block.iter()
+        .filter(|s| !o.import.as_ref().is_some_and(|i| i.covers((s.0 as f32, s.1 as f32))))
+        .cloned()
+        .collect();
+    let floats: Vec<(f32, f32)> = seeds.iter().map(|s| (s.0 as f32, s.1 as f32)).collect();
+    seeds.into_iter().zip(map::genhightmaps(&floats, o.seed)).collect()
+}
+
+/// generate one chunk and write its outputs, returns what the stitched outputs need if there are any
+/// base is its procedural hights if they were generated ahead of time
+fn write_chunk(o: &Options, tiles: Option<&Tiles>, pois: &mut Pois, seed: (i32, i32), base: Option<ChunkData<f32>>) -> Result<Option<Kept>, String> {
+    let chunk = Chunk::generate_with(o.seed, seed, o.import.as_ref(), pois, |s| base.unwrap_or_else(|| map::genhightmap(s, o.seed)));
+    let path = |ext: &str| o.out.join(format!("{}_{}.{}", seed.0, seed.1, ext));
+    let glb = o.mesh_format == MeshFormat::Glb;
+    let stitched_texture = glb && o.outputs.contains(&Output::StitchedMesh);
+    // texturing is as slow as generating, so it is done at most once
+    let needs_texture = stitched_texture || o.outputs.iter().any(|out| match out {
+        Output::Material | Output::Tiles => true,
+        Output::Mesh => glb,
+        _ => false,
+    });
+    let texture = needs_texture.then(|| chunk.texture(o.seed, tiles.unwrap()));
+    let texture = || texture.clone().unwrap();
+    for output in o.outputs.iter() {
+        let heightmap = || Heightmap::stitch(seed, seed, |_| chunk.hightmap.clone());
+        match output {
+            Output::Height => heightmap().write(&path(&format!("height.{}", o.height_format.extension())), o.height_format)?,
+            Output::Slope => export::write_f32(&path("slope.f32"), &chunk.slope[..])?,
+            Output::Packed => Packed::pack(&chunk.hightmap).write(&path("height.packed"))?,
+            Output::Material => export::write_png(&path("material.png"), texture())?,
+            Output::Mesh => write_mesh(o, &path(o.mesh_format.extension()), &chunk.mesh(), &heightmap(), texture)?,
+            Output::Tiles => {
+                let pyramid = Pyramid::new(o.from, o.to);
+                let tile = pyramid::chunk_tile(texture(), &chunk.hightmap)?;
+                pyramid::write_tile(&Pyramid::path(&o.out.join("tiles"), pyramid.max_zoom, pyramid.tile(seed)), &tile)?
+            }
+            // written once all chunks are done
+            Output::Stitched | Output::StitchedMesh => (),
+        }
+    }
+    let stitched = o.outputs.contains(&Output::Stitched) || o.outputs.contains(&Output::StitchedMesh);
+    Ok(stitched.then(|| Kept {hightmap: chunk.hightmap, texture: stitched_texture.then(texture)}))
+}
+
+fn run(o: &Options) -> Result<(), String> {
+    std::fs::create_dir_all(&o.out).map_err(|e| format!("cant create {}: {}", o.out.display(), e))?;
+    let textured = o.mesh_format == MeshFormat::Glb && (o.outputs.contains(&Output::Mesh) || o.outputs.contains(&Output::StitchedMesh));
+    let tiles = if o.outputs.contains(&Output::Material) || o.outputs.contains(&Output::Tiles) || textured {
+        Some(Tiles::load(Path::new(&o.assets))?)
+    } else {
+        None
+    };
+    let chunks: Vec<(i32, i32)> = (o.from.1..=o.to.1)
+        .flat_map(|z| (o.from.0..=o.to.0).map(move |x| (x, z)))
+        .collect();
+    // every thread takes every n'th chunk, chunks do not depend on each other so the order does not matter
+    // the hights the stitched outputs need are kept, so no chunk is generated twice
+    let results: Vec<Worker> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..o.threads).map(|t| {
+            let chunks = &chunks;
+            let tiles = tiles.as_ref();
+            s.spawn(move || {
+                let mut pois = Pois::default();
+                let mut kept = Vec::new();
+                let mine: Vec<(i32, i32)> = chunks.iter().skip(t).step_by(o.threads).cloned().collect();
+                for block in mine.chunks(BATCH_CHUNKS) {
+                    let mut bases = batch_bases(o, block);
+                    for seed in block {
+                        if let Some(k) = write_chunk(o, tiles, &mut pois, *seed, bases.remove(seed))? {
+                            kept.push((*seed, k));
+                        }
+                    }
+                }
+                Ok(kept)
+            })
+        }).collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+    let mut kept = HashMap::new();
+    for r in results {
+        kept.extend(r?);
+    }
+    let total = chunks.len();
+    if o.outputs.contains(&Output::Stitched) || o.outputs.contains(&Output::StitchedMesh) {
+        let stitched = Heightmap::stitch(o.from, o.to, |seed| kept[&seed].hightmap.clone());
+        if o.outputs.contains(&Output::Stitched) {
+            stitched.write(&o.out.join(format!("heightmap.{}", o.height_format.extension())), o.height_format)?;
+        }
+        if o.outputs.contains(&Output::StitchedMesh) {
+            // caves are left out, the region is meshed from the hights
+            let texture = || export::stitch_textures(o.from, o.to, |seed| kept.remove(&seed).and_then(|k| k.texture).unwrap());
+            write_mesh(o, &o.out.join(format!("region.{}", o.mesh_format.extension())), &stitched.mesh(), &stitched, texture)?;
+        }
+    }
+    if o.outputs.contains(&Output::Tiles) {
+        // the chunk tiles are the deepest zoom, the rest is made from them
+        Pyramid::new(o.from, o.to).downsample_all(&o.out.join("tiles"))?;
+    }
+    println!("wrote {} chunks to {}", total, o.out.display());
+    Ok(())
+}
+
+/// generate chunks when they are asked for, --from and --to are the range of the tile pyramid
+fn serve(o: Options) -> Result<(), String> {
+    let tiles = Tiles::load(Path::new(&o.assets))?;
+    let listener = TcpListener::bind(&o.addr).map_err(|e| format!("cant listen on {}: {}", o.addr, e))?;
+    println!("serving world {} on http://{}", o.seed, o.addr);
+    let server = Server::new(o.seed, o.import, tiles, Pyramid::new(o.from, o.to), o.cache);
+    Arc::new(server).serve(listener, o.threads)
+}
+
+/// stream chunks and edits to games
+fn host(o: Options) -> Result<(), String> {
+    let listener = TcpListener::bind(&o.addr).map_err(|e| format!("cant listen on {}: {}", o.addr, e))?;
+    println!("hosting world {} on {}", o.seed, o.addr);
+    Arc::new(net::Server::new(o.seed, o.import)).serve(listener)
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let command = match args.first().map(|a| a.as_str()) {
+        Some("serve") | Some("host") => Some(args.remove(0)),
+        _ => None,
+    };
+    let result = parse(&args).and_then(|o| match command.as_deref() {
+        Some("serve") => serve(o),
+        Some("host") => host(o),
+        _ => run(&o),
+    });
+    if let Err(e) = result {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,190 @@
+//! saved camera positions, and teleporting to them or to coordinates
+
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use serde::{Deserialize, Serialize};
+use crate::chunk::WorldSeed;
+use crate::console::Command;
+use crate::input::{Action, ActionState};
+use crate::map;
+
+/// where bookmarks are saved, relative to the working directory
+pub const BOOKMARKS_FILE: &str = "bookmarks.ron";
+
+/// a saved camera
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Vec3,
+    pub rotation: Quat,
+    /// the world seed the bookmark was made in
+    pub seed: u32,
+}
+
+/// all bookmarks, kept in sync with BOOKMARKS_FILE
+#[derive(Debug, Default, Resource, Serialize, Deserialize)]
+pub struct Bookmarks {
+    pub list: Vec<Bookmark>,
+    /// the last bookmark visited, for cycling
+    #[serde(skip)]
+    current: usize,
+}
+
+impl Bookmarks {
+    /// read bookmarks from a file
+    pub fn load(path: &str) -> Result<Bookmarks, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("cant read {}: {}", path, e))?;
+        ron::from_str(&s).map_err(|e| format!("cant parse {}: {}", path, e))
+    }
+
+    /// write bookmarks to a file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let s = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new()).unwrap();
+        std::fs::write(path, s).map_err(|e| format!("cant write {}: {}", path, e))
+    }
+
+    /// add a bookmark, replacing any with the same name
+    pub fn insert(&mut self, b: Bookmark) {
+        match self.list.iter_mut().find(|x| x.name == b.name) {
+            Some(x) => *x = b,
+            None => self.list.push(b),
+        }
+    }
+
+    /// step through the bookmarks, wrapping arround
+    fn cycle(&mut self, step: isize) -> Option<&Bookmark> {
+        if self.list.is_empty() {
+            return None;
+        }
+        let n = self.list.len() as isize;
+        self.current = (self.current as isize + step).rem_euclid(n) as usize;
+        self.list.get(self.current)
+    }
+}
+
+/// move the camera, and switch world if seed is set
+pub struct Teleport {
+    pub position: Vec3,
+    /// keep the current rotation if None
+    pub rotation: Option<Quat>,
+    pub seed: Option<u32>,
+}
+
+impl Teleport {
+    fn to_bookmark(b: &Bookmark) -> Teleport {
+        Teleport {
+            position: b.position,
+            rotation: Some(b.rotation),
+            seed: Some(b.seed),
+        }
+    }
+}
+
+/// load bookmarks from BOOKMARKS_FILE if it exists
+pub fn init(mut commands: Commands) {
+    let b = Bookmarks::load(BOOKMARKS_FILE).unwrap_or_else(|e| {
+        println!("{}, starting without bookmarks", e);
+        Bookmarks::default()
+    });
+    commands.insert_resource(b);
+}
+
+/// save and cycle through bookmarks with hotkeys
+pub fn hotkeys(
+    state: Res<ActionState>,
+    seed: Res<WorldSeed>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut teleports: EventWriter<Teleport>,
+    cameras: Query<&Transform, With<Camera>>,
+) {
+    if state.just_pressed(Action::SaveBookmark) {
+        if let Some(c) = cameras.iter().next() {
+            let name = format!("bookmark {}", bookmarks.list.len() + 1);
+            save(&mut bookmarks, name, c, seed.0);
+        }
+    }
+    let step = state.just_pressed(Action::NextBookmark) as isize - state.just_pressed(Action::PreviousBookmark) as isize;
+    if step != 0 {
+        if let Some(b) = bookmarks.cycle(step) {
+            println!("going to {}", b.name);
+            teleports.send(Teleport::to_bookmark(b));
+        }
+    }
+}
+
+fn save(bookmarks: &mut Bookmarks, name: String, camera: &Transform, seed: u32) {
+    println!("saved {}", name);
+    bookmarks.insert(Bookmark {
+        name,
+        position: camera.translation,
+        rotation: camera.rotation,
+        seed,
+    });
+    if let Err(e) = bookmarks.save(BOOKMARKS_FILE) {
+        println!("{}", e);
+    }
+}
+
+/// handle the bookmark and teleport console commands
+pub fn commands(
+    mut commands: EventReader<Command>,
+    seed: Res<WorldSeed>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut teleports: EventWriter<Teleport>,
+    cameras: Query<&Transform, With<Camera>>,
+) {
+    for c in commands.iter() {
+        match c {
+            Command::Teleport(p) => teleports.send(Teleport {position: *p, rotation: None, seed: None}),
+            Command::TeleportChunk(x, z) => {
+                // the middle of the chunk, at the current hight
+                let s = map::getchunksize();
+                let y = cameras.iter().next().map_or(10.0, |c| c.translation.y);
+                let position = Vec3::new((*x as f32 + 0.5) * s, y, (*z as f32 + 0.5) * s);
+                teleports.send(Teleport {position, rotation: None, seed: None});
+            }
+            Command::Save(name) => {
+                if let Some(c) = cameras.iter().next() {
+                    save(&mut bookmarks, name.clone(), c, seed.0);
+                }
+            }
+            Command::Goto(name) => match bookmarks.list.iter().position(|b| &b.name == name) {
+                Some(i) => {
+                    bookmarks.current = i;
+                    teleports.send(Teleport::to_bookmark(&bookmarks.list[i]));
+                }
+                None => println!("no bookmark called {}", name),
+            },
+            Command::List => {
+                for b in bookmarks.list.iter() {
+                    println!("{}: {} (seed {})", b.name, b.position, b.seed);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// move the camera, the loader picks up the new position and generates the nearest chunks first
+pub fn teleport(
+    mut teleports: EventReader<Teleport>,
+    mut seed: ResMut<WorldSeed>,
+    mut fly: ResMut<crate::input::FlyCamera>,
+    mut cameras: Query<&mut Transform, With<Camera>>,
+) {
+    for t in teleports.iter() {
+        if let Some(s) = t.seed {
+            if s != seed.0 {
+                println!("switching to world seed {}", s);
+                seed.0 = s;
+            }
+        }
+        fly.velocity = Vec3::ZERO;
+        for mut c in cameras.iter_mut() {
+            c.translation = t.position;
+            if let Some(r) = t.rotation {
+                c.rotation = r;
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! caves and overhangs, a 3d density field for chunks where the cave mask is active
+//! the density is the hightmap (solid below the surface) with tunnels carved out of it,
+//! so the surface only changes where tunnels break through it and chunks without caves keep the hightmap mesh.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, Mesh};
+use bevy::render::render_resource::PrimitiveTopology;
+use noise::{NoiseFn, Perlin, Seedable};
+use std::collections::HashMap;
+use crate::map::{ChunkData, CHUNK_SIZE, CHUNK_SQSIZE, VOXEL_SCALE};
+
+/// tunnels are carved at most this far below the surface, in points
+pub const CAVE_DEPTH: f32 = 12.0;
+/// seeds for the noise, so caves do not line up with the terrain noise
+const MASK_SEED: u32 = 7001;
+const TUNNEL_SEED: u32 = 7002;
+/// the mask varies over this many points
+const MASK_SCALE: f64 = 200.0;
+/// tunnels wind over this many points
+const TUNNEL_SCALE: f64 = 24.0;
+/// how wide tunnels are, in noise units
+const TUNNEL_WIDTH: f32 = 0.08;
+/// converts the tunnel noise into density, roughly points
+const TUNNEL_STRENGTH: f32 = 20.0;
+
+/// corners of a cell, corner i is at (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+const CORNERS: [(usize, usize, usize); 8] = [
+    (0, 0, 0), (1, 0, 0), (0, 1, 0), (1, 1, 0),
+    (0, 0, 1), (1, 0, 1), (0, 1, 1), (1, 1, 1),
+];
+/// a cell split into 6 tetrahedra arround its diagonal from corner 0 to 7
+/// neighbouring cells split their shared faces the same way, so the surface has no cracks
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7], [0, 1, 5, 7], [0, 2, 3, 7],
+    [0, 2, 6, 7], [0, 4, 5, 7], [0, 4, 6, 7],
+];
+
+/// how much a point may have caves, 0.0 for none, from the world position of the point (points) and its hight
+fn mask(noise: &Perlin, x: f32, z: f32, h: f32) -> f32 {
+    let n = noise.get([x as f64 / MASK_SCALE, z as f64 / MASK_SCALE]) as f32;
+    // only in hills, caves under the sea would just fill with water
+    smoothstep(0.25, 0.45, n) * smoothstep(2.0, 6.0, h)
+}
+
+fn smoothstep(a: f32, b: f32, x: f32) -> f32 {
+    let t = ((x - a) / (b - a)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// the density field of a chunk, positive is solid, the grid spacing is one point in every direction
+#[derive(Debug, Clone)]
+pub struct Volume {
+    /// hight of the lowest layer, in points
+    pub min_y: i32,
+    /// number of layers
+    pub layers: usize,
+    /// x + z * CHUNK_SIZE + y * CHUNK_SQSIZE
+    pub density: Vec<f32>,
+}
+
+/// the density field for a chunk, None where the cave mask is off everywhere in the chunk
+/// seed is the chunk seed after world_chunk
+pub fn genvolume(hightmap: &ChunkData<f32>, seed: (f32, f32)) -> Option<Volume> {
+    let mask_noise = Perlin::new().set_seed(MASK_SEED);
+    let ox = seed.0 * (CHUNK_SIZE - 1) as f32;
+    let oz = seed.1 * (CHUNK_SIZE - 1) as f32;
+    let masks: Vec<f32> = (0..CHUNK_SQSIZE)
+        .map(|i| mask(&mask_noise, ox + (i % CHUNK_SIZE) as f32, oz + (i / CHUNK_SIZE) as f32, hightmap[i]))
+        .collect();
+    if masks.iter().all(|m| *m <= 0.0) {
+        return None;
+    }
+
+    let tunnel_a = Perlin::new().set_seed(TUNNEL_SEED);
+    let tunnel_b = Perlin::new().set_seed(TUNNEL_SEED + 1);
+    let lo = hightmap.iter().cloned().fold(f32::MAX, f32::min);
+    let hi = hightmap.iter().cloned().fold(f32::MIN, f32::max);
+    // a solid layer at the bottom and an empty one at the top, so the surface is closed
+    let min_y = (lo - CAVE_DEPTH).floor() as i32 - 1;
+    let layers = ((hi.ceil() as i32 + 2) - min_y) as usize;
+    let mut density = vec![0.0; CHUNK_SQSIZE * layers];
+    for (i, d) in density.iter_mut().enumerate() {
+        let column = i % CHUNK_SQSIZE;
+        let y = (i / CHUNK_SQSIZE) as i32 + min_y;
+        let h = hightmap[column];
+        let surface = h - y as f32;
+        *d = if masks[column] <= 0.0 || !(-1.0..=CAVE_DEPTH).contains(&surface) {
+            surface
+        } else {
+            // tunnels are where both noises are near zero, squashed so they run more sideways than up
+            let p = [
+                (ox + (column % CHUNK_SIZE) as f32) as f64 / TUNNEL_SCALE,
+                y as f64 * 2.0 / TUNNEL_SCALE,
+                (oz + (column / CHUNK_SIZE) as f32) as f64 / TUNNEL_SCALE,
+            ];
+            let tunnel = (tunnel_a.get(p).abs().max(tunnel_b.get(p).abs()) as f32 - TUNNEL_WIDTH * masks[column]) * TUNNEL_STRENGTH;
+            // close off the bottom of the caves
+            let floor = CAVE_DEPTH - surface;
+            surface.min(tunnel.max(-floor))
+        };
+    }
+    Some(Volume {min_y, layers, density})
+}
+
+impl Volume {
+    fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.density[x + z * CHUNK_SIZE + y * CHUNK_SQSIZE]
+    }
+
+    /// the density gradient at a grid point, by central differences
+    fn gradient(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        let d = |a: usize, b: usize, f: &dyn Fn(usize) -> f32| (f(b) - f(a)) / (b - a).max(1) as f32;
+        Vec3::new(
+            d(x.saturating_sub(1), (x + 1).min(CHUNK_SIZE - 1), &|x| self.get(x, y, z)),
+            d(y.saturating_sub(1), (y + 1).min(self.layers - 1), &|y| self.get(x, y, z)),
+            d(z.saturating_sub(1), (z + 1).min(CHUNK_SIZE - 1), &|z| self.get(x, y, z)),
+        )
+    }
+
+    /// the hights of every floor (solid below, air above) at a point, highest first, in points
+    pub fn surfaces(&self, x: usize, z: usize) -> Vec<f32> {
+        (1..self.layers).rev().filter_map(|y| {
+            let above = self.get(x, y, z);
+            let below = self.get(x, y - 1, z);
+            (above <= 0.0 && below > 0.0).then(|| (y - 1) as f32 + self.min_y as f32 + below / (below - above))
+        }).collect()
+    }
+
+    /// mesh the zero surface of the density with marching tetrahedra, in the same space as chunktomesh
+    /// roads is the road cover from Roads::grade, painted into the vertex colours like for hightmap chunks
+    pub fn mesh(&self, roads: Option<&ChunkData<f32>>) -> Mesh {
+        let mut position: Vec<[f32; 3]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut uvs: Vec<[f32; 2]> = Vec::new();
+        let mut colours: Vec<[f32; 4]> = Vec::new();
+        let mut indeces: Vec<u32> = Vec::new();
+        // vertices are shared between triangles on the same grid edge
+        let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+        let index = |x: usize, y: usize, z: usize| x + z * CHUNK_SIZE + y * CHUNK_SQSIZE;
+        let point = |i: usize| (i % CHUNK_SIZE, i / CHUNK_SQSIZE, (i / CHUNK_SIZE) % CHUNK_SIZE);
+
+        for y in 0..self.layers - 1 {
+            for z in 0..CHUNK_SIZE - 1 {
+                for x in 0..CHUNK_SIZE - 1 {
+                    let corners = CORNERS.map(|(cx, cy, cz)| index(x + cx, y + cy, z + cz));
+                    let solid = corners.map(|i| self.density[i] > 0.0);
+                    if solid.iter().all(|s| *s) || solid.iter().all(|s| !*s) {
+                        continue;
+                    }
+                    for t in TETRAHEDRA {
+                        let (inside, outside): (Vec<usize>, Vec<usize>) = t.iter().map(|c| corners[*c]).partition(|i| self.density[*i] > 0.0);
+                        // the edges between solid and air corners, a triangle or a quad
+                        let crossing: Vec<(usize, usize)> = match (inside.len(), outside.len()) {
+                            (1, 3) => outside.iter().map(|o| (inside[0], *o)).collect(),
+                            (3, 1) => inside.iter().map(|i| (*i, outside[0])).collect(),
+                            (2, 2) => vec![(inside[0], outside[0]), (inside[0], outside[1]), (inside[1], outside[1]), (inside[1], outside[0])],
+                            _ => continue,
+                        };
+                        let mut vertex = |(a, b): (usize, usize)| -> u32 {
+                            let key = (a.min(b), a.max(b));
+                            *edges.entry(key).or_insert_with(|| {
+                                let (da, db) = (self.density[a], self.density[b]);
+                                let f = da / (da - db);
+                                let (pa, pb) = (point(a), point(b));
+                                let pa = Vec3::new(pa.0 as f32, pa.1 as f32, pa.2 as f32);
+                                let pb = Vec3::new(pb.0 as f32, pb.1 as f32, pb.2 as f32);
+                                let p = pa + (pb - pa) * f;
+                                let (ga, gb) = (self.gradient(point(a).0, point(a).1, point(a).2), self.gradient(point(b).0, point(b).1, point(b).2));
+                                // density falls going out of the ground
+                                let n = -(ga + (gb - ga) * f).normalize_or_zero();
+                                position.push([p.x * VOXEL_SCALE, (p.y + self.min_y as f32) * VOXEL_SCALE, p.z * VOXEL_SCALE]);
+                                normals.push([n.x, n.y, n.z]);
+                                uvs.push([p.x / CHUNK_SIZE as f32, p.z / CHUNK_SIZE as f32]);
+                                let nearest = (p.x.round() as usize).min(CHUNK_SIZE - 1) + (p.z.round() as usize).min(CHUNK_SIZE - 1) * CHUNK_SIZE;
+                                colours.push([roads.map_or(0.0, |r| r[nearest]), 0.0, 0.0, 1.0]);
+                                (position.len() - 1) as u32
+                            })
+                        };
+                        let v: Vec<u32> = crossing.into_iter().map(&mut vertex).collect();
+                        let mut tris = vec![[v[0], v[1], v[2]]];
+                        if v.len() == 4 {
+                            tris.push([v[0], v[2], v[3]]);
+                        }
+                        for mut tri in tris {
+                            // face the triangle out of the ground, the same way as its normals
+                            let p = tri.map(|i| Vec3::from(position[i as usize]));
+                            let n: Vec3 = tri.iter().map(|i| Vec3::from(normals[*i as usize])).sum();
+                            if (p[1] - p[0]).cross(p[2] - p[0]).dot(n) < 0.0 {
+                                tri.swap(1, 2);
+                            }
+                            indeces.extend(tri);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        if roads.is_some() {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colours);
+        }
+        mesh.set_indices(Some(Indices::U32(indeces)));
+        mesh
+    }
+}
+
+#[test]
+fn caves_keep_the_surface_and_add_floors() {
+    // a flat hill, in a spot where the mask is on
+    let hightmap = Box::new([10.0_f32; CHUNK_SQSIZE]);
+    let noise = Perlin::new().set_seed(MASK_SEED);
+    let (x, z) = (0..1000).map(|i| (i as f32 * 37.0, i as f32 * -23.0))
+        .find(|(x, z)| mask(&noise, *x, *z, 10.0) >= 1.0).unwrap();
+    let seed = (x / (CHUNK_SIZE - 1) as f32, z / (CHUNK_SIZE - 1) as f32);
+    let v = genvolume(&hightmap, (seed.0.floor(), seed.1.floor())).unwrap();
+    let surfaces: Vec<Vec<f32>> = (0..CHUNK_SQSIZE).map(|i| v.surfaces(i % CHUNK_SIZE, i / CHUNK_SIZE)).collect();
+    // most columns still have the hightmap surface on top, and some have a cave floor under it
+    assert!(surfaces.iter().filter(|s| s.first().is_some_and(|h| (h - 10.0).abs() < 0.01)).count() > CHUNK_SQSIZE / 2);
+    assert!(surfaces.iter().any(|s| s.len() > 1));
+    assert!(!v.mesh(None).indices().unwrap().is_empty());
+    // no caves in the sea
+    assert!(genvolume(&Box::new([-2.0_f32; CHUNK_SQSIZE]), (0.0, 0.0)).is_none());
+}
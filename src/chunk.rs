@@ -2,19 +2,38 @@
 
 use bevy::render::mesh::Mesh;
 use bevy::prelude::*;
+use crate::cave::{self, Volume};
+use crate::heightmap::Imported;
 use crate::map;
+use crate::net::Remote;
+use crate::packed::Cache;
+use crate::material::{TerrainAssets, TerrainMaterial};
+use crate::poi::Pois;
+use crate::road::Roads;
+use crate::water::{self, WaterAssets};
+
+/// the world seed chunks are generated with, changing it reloads all chunks
+#[derive(Resource, Default)]
+pub struct WorldSeed(pub u32);
+
+/// how many chunks may be generated per frame, so nearby chunks are not held up by far ones
+pub const GENERATE_PER_FRAME: usize = 2;
 
 /// the componet represienting a chunk
 #[derive(Component)]
 pub struct Map {
     pub hightmap: Option<map::ChunkData<f32>>,
-    tex: Option<Image>,
+    /// how much road covers each point, None without roads
+    pub roads: Option<map::ChunkData<f32>>,
+    /// the cave density field, None for chunks without caves
+    pub volume: Option<Volume>,
     mesh: Option<Mesh>,
+    /// the terrain material, debug views swap it out
+    pub material: Option<Handle<TerrainMaterial>>,
     pub render: Option<Entity>,
     pub this: Option<Entity>,
-//    wireframe: bool,
-    transform: Transform,
-    seed: (f32,f32)
+    pub transform: Transform,
+    pub seed: (f32,f32)
 }
 
 impl Map {
@@ -23,63 +42,134 @@ impl Map {
     pub fn new_with_transform(t: Transform,seed: (f32,f32), e: Entity) -> Map {
         Map {
             hightmap: None, 
-            tex: None, 
+            roads: None,
+            volume: None,
             mesh: None, 
+            material: None,
             render: None,
             this: Some(e),
-  //          wireframe: false,
             transform: t,
             seed
         }
     }
+
+    /// the mesh the chunk was rendered with, None until it is generated
+    pub fn mesh(&self) -> Option<&Mesh> {
+        self.mesh.as_ref()
+    }
+
+    /// the hights of every floor at a point in the chunk, highest first, in points
+    /// only chunks with caves have more than one, empty until the chunk is generated
+    pub fn surfaces(&self, x: usize, z: usize) -> Vec<f32> {
+        match (&self.volume, &self.hightmap) {
+            (Some(v), _) => v.surfaces(x, z),
+            (None, Some(h)) => vec![h[x + z * map::CHUNK_SIZE]],
+            (None, None) => Vec::new(),
+        }
+    }
 }
 
-/// generate and load maps for all entitys with The map component 
+/// generate and load maps for all entitys with The map component, nearest to the camera first
+/// waits for the terrain material to be built
+/// chunks with points under sea level get a water surface as a child of the render entity
+/// poi footprints are flattened, then roads are graded into the hightmap and painted with the mesh's vertex colours
+/// the hights before roads are kept packed in the Cache, so chunks that come back into view are not generated again
+/// chunks covered by an imported heightmap use its hights, without pois or roads since those follow the procedural hights
+/// chunks under the cave mask are meshed from their density field instead of the hightmap
+/// with a Remote the hights come from the server, chunks wait until they arrive and have no pois or roads
+/// procedural chunks wait until the pois arround them are worked out
+#[allow(clippy::too_many_arguments)]
 pub fn generate_maps(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     mut maps: Query<&mut Map>,
-    mut textures: ResMut<Assets<Image>>,
-
+    assets: Res<TerrainAssets>,
+    water: Res<WaterAssets>,
+    roads: Res<Roads>,
+    mut pois: ResMut<Pois>,
+    imported: Res<Imported>,
+    mut remote: Option<ResMut<Remote>>,
+    mut cache: ResMut<Cache>,
+    world: Res<WorldSeed>,
+    cameras: Query<&Transform, With<Camera>>,
 ) {
-    for mut map in maps.iter_mut() {
-        match map.hightmap {
-            Some(_) => continue,
-            None => ()
+    let material = match &assets.material {
+        Some(m) => m.clone(),
+        None => return
+    };
+    
+    let eye = cameras.iter().next().map_or(Vec3::ZERO, |c| c.translation);
+    let remote_hights = remote.is_some();
+    let mut pending: Vec<_> = maps.iter_mut()
+        .filter(|m| m.hightmap.is_none())
+        .filter(|m| remote.as_mut().is_none_or(|r| r.has((m.seed.0 as i32, m.seed.1 as i32))))
+        // procedural chunks wait for the pois that flatten them, they are worked out in the background
+        .filter(|m| remote_hights || imported.0.as_ref().is_some_and(|i| i.covers(m.seed)) || pois.ready(world.0, m.seed))
+        .collect();
+    let dist = |m: &Map| (m.transform.translation - eye).length();
+    pending.sort_by(|a, b| dist(a).total_cmp(&dist(b)));
+    
+    for mut map in pending.into_iter().take(GENERATE_PER_FRAME) {
+        let from_server = remote.as_ref().and_then(|r| r.hights((map.seed.0 as i32, map.seed.1 as i32)));
+        let from_elsewhere = from_server.or_else(|| imported.0.as_ref().and_then(|i| i.chunk(map.seed)));
+        // server and imported hights are not clipped to sea level by genchunk, the water is as deep as they are
+        let procedural = from_elsewhere.is_none();
+        let (h, road) = match from_elsewhere {
+            Some(h) => (h, None),
+            None => {
+                let seed = (map.seed.0 as i32, map.seed.1 as i32);
+                let mut h = match cache.get(world.0, seed) {
+                    Some(h) => h,
+                    None => {
+                        let mut h = map::genhightmap(map.seed,world.0);
+                        pois.flatten(world.0, &mut h, map.seed);
+                        cache.insert(world.0, seed, &h);
+                        h
+                    }
+                };
+                let road = roads.grade(&mut h, map.seed);
+                (h, road)
+            }
         };
-        let (t, m, h) = match map::gen(&mut textures,map.seed) {
-            Ok(x) => x,
-            Err(e) => {
-                println!("{}",e);
-                continue;
-            },
+        let volume = cave::genvolume(&h, map::world_chunk(map.seed, world.0));
+        let m = match &volume {
+            Some(v) => v.mesh(road.as_ref()),
+            None => {
+                let mut m = map::chunktomesh(&h);
+                if let Some(r) = &road {
+                    // the terrain shader reads the road cover from red
+                    let colours: Vec<[f32; 4]> = r.iter().map(|c| [*c, 0.0, 0.0, 1.0]).collect();
+                    m.insert_attribute(Mesh::ATTRIBUTE_COLOR, colours);
+                }
+                m
+            }
         };
+        // procedural chunks are clipped to sea level, the water is as deep as the ground under that
+        let seabed = (procedural && h.iter().any(|h| *h <= map::SEA_LEVEL)).then(|| map::genseabedmap(map.seed, world.0));
+        let surface = water::water_mesh(&h, seabed.as_ref(), map::SEA_LEVEL);
         map.hightmap = Some(h);
+        map.roads = road;
+        map.volume = volume;
         map.mesh = Some(m.clone());
-        map.tex = Some(t.clone());
         println!("map generated!");
         
-        let tex_handle = textures.add(t);
-     
-        let material_handle = materials.add(StandardMaterial {
-            base_color_texture: Some(tex_handle.clone()),
-            unlit: false,
-            perceptual_roughness: 0.9,
-            metallic: 0.0,
-            ..Default::default()
-        });
-        
-        let mut w = commands.spawn(PbrBundle {
+        let mut w = commands.spawn(MaterialMeshBundle {
             mesh: meshes.add(m),
-            material: material_handle,
+            material: material.clone(),
             transform: map.transform,
             ..Default::default()
         });
+        if let Some(s) = surface {
+            w.with_children(|c| {
+                c.spawn(MaterialMeshBundle {
+                    mesh: meshes.add(s),
+                    material: water.material.clone(),
+                    ..Default::default()
+                });
+            });
+        }
         
-//         if map.wireframe {
-//             w.insert(Wireframe);
-//         }
+        map.material = Some(material.clone());
         map.render = Some(w.id());
         
         println!("map added to renderer")
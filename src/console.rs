@@ -0,0 +1,133 @@
+//! commands typed into the terminal the program was started from
+
+use bevy::prelude::*;
+use std::io::BufRead;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Mutex;
+
+/// a parsed console command, sent as an event
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// tp x y z, move the camera to a world coordinate
+    Teleport(Vec3),
+    /// tpc x z, move the camera over a chunk
+    TeleportChunk(i32, i32),
+    /// save name, bookmark the camera
+    Save(String),
+    /// goto name, jump to a bookmark
+    Goto(String),
+    /// bookmarks, list the bookmarks
+    List,
+    /// view n, load n chunks arround the camera
+    ViewDistance(i32),
+    /// aerial, toggle aerial perspective
+    ToggleAerial,
+    /// time h, set the time of day in hours
+    Time(f32),
+    /// road x z x z ..., build a road through world coordinates
+    Road(Vec<Vec2>),
+    /// clearroads, remove all roads
+    ClearRoads,
+    /// pois, list points of interest near the camera
+    ListPois,
+    /// raise h r, raise the ground within r points of the camera by h points, through the server
+    Raise(f32, f32),
+}
+
+impl Command {
+    /// parse a line of input
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let num = |i: usize| -> Result<f32, String> {
+            words.get(i)
+                .ok_or(format!("{}: missing argument", words[0]))?
+                .parse::<f32>()
+                .map_err(|e| format!("{}: {}", words[0], e))
+        };
+        // chunks and distances are whole, 1.7 is an error rather than 1
+        let int = |i: usize| -> Result<i32, String> {
+            words.get(i)
+                .ok_or(format!("{}: missing argument", words[0]))?
+                .parse::<i32>()
+                .map_err(|e| format!("{}: {}", words[0], e))
+        };
+        match words.first() {
+            Some(&"tp") => Ok(Command::Teleport(Vec3::new(num(1)?, num(2)?, num(3)?))),
+            Some(&"tpc") => Ok(Command::TeleportChunk(int(1)?, int(2)?)),
+            Some(&"save") if words.len() > 1 => Ok(Command::Save(words[1..].join(" "))),
+            Some(&"goto") if words.len() > 1 => Ok(Command::Goto(words[1..].join(" "))),
+            Some(&"bookmarks") => Ok(Command::List),
+            Some(&"view") => match int(1)? {
+                d if d > 0 => Ok(Command::ViewDistance(d)),
+                _ => Err("view: the distance must be at least 1".to_string()),
+            },
+            Some(&"aerial") => Ok(Command::ToggleAerial),
+            Some(&"time") => Ok(Command::Time(num(1)?)),
+            Some(&"road") if words.len() >= 5 && words.len() % 2 == 1 => {
+                let waypoints: Result<Vec<Vec2>, String> = (1..words.len()).step_by(2)
+                    .map(|i| Ok(Vec2::new(num(i)?, num(i + 1)?)))
+                    .collect();
+                Ok(Command::Road(waypoints?))
+            }
+            Some(&"clearroads") => Ok(Command::ClearRoads),
+            Some(&"pois") => Ok(Command::ListPois),
+            Some(&"raise") => Ok(Command::Raise(num(1)?, num(2)?)),
+            Some(c) => Err(format!("unknown command or missing argument: {}", c)),
+            None => Err("empty command".to_string()),
+        }
+    }
+}
+
+/// lines read from stdin by a background thread
+#[derive(Resource)]
+pub struct Console {
+    lines: Mutex<Receiver<String>>
+}
+
+/// start reading stdin
+pub fn init(mut commands: Commands) {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(l) => if tx.send(l).is_err() {return},
+                Err(_) => return,
+            }
+        }
+    });
+    commands.insert_resource(Console {
+        lines: Mutex::new(rx)
+    });
+}
+
+/// parse any lines typed since the last frame and send them as events
+pub fn poll(
+    console: Res<Console>,
+    mut events: EventWriter<Command>,
+) {
+    let lines = console.lines.lock().unwrap();
+    for line in lines.try_iter() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Command::parse(&line) {
+            Ok(c) => events.send(c),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+#[test]
+fn parses_commands() {
+    assert_eq!(Command::parse("tp 1 2.5 -3"), Ok(Command::Teleport(Vec3::new(1.0, 2.5, -3.0))));
+    assert_eq!(Command::parse("tpc -4 7"), Ok(Command::TeleportChunk(-4, 7)));
+    assert!(Command::parse("tpc 1.7 2.2").is_err());
+    assert_eq!(Command::parse("save big hill"), Ok(Command::Save("big hill".to_string())));
+    assert!(Command::parse("tp 1 2").is_err());
+    assert!(Command::parse("save").is_err());
+    assert_eq!(Command::parse("view 4"), Ok(Command::ViewDistance(4)));
+    assert!(Command::parse("view 0").is_err());
+    assert_eq!(Command::parse("road 0 0 10 -5"), Ok(Command::Road(vec![Vec2::new(0.0, 0.0), Vec2::new(10.0, -5.0)])));
+    assert!(Command::parse("road 0 0 10").is_err());
+    assert_eq!(Command::parse("raise 2 5"), Ok(Command::Raise(2.0, 5.0)));
+}
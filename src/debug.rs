@@ -0,0 +1,312 @@
+//! debug views of the terrain, for finding seams and checking what the generator does
+
+use bevy::pbr::wireframe::Wireframe;
+use bevy::prelude::*;
+use bevy::render::mesh::Mesh;
+use bevy::render::render_resource::{Extent3d, PrimitiveTopology, TextureDimension, TextureFormat};
+use crate::chunk::{Map, WorldSeed};
+use crate::input::{Action, ActionState};
+use crate::map::{self, ChunkData, CHUNK_SIZE, VOXEL_SCALE};
+use crate::material::TerrainMaterial;
+use crate::reg;
+use crate::mix;
+
+/// the font used for labels
+pub const ASSETS_FONT: &str = "fonts/DejaVuSansMono.ttf";
+
+/// what the terrain is drawn as, CycleView steps through these in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Resource)]
+pub enum ViewMode {
+    #[default]
+    Textured,
+    Wireframe,
+    /// blue at sea level to white on the peaks
+    Height,
+    /// genslope output, red at the stone threshold
+    Slope,
+    /// mesh normals as rgb
+    Normals,
+    /// region factors from reg::Regdata in red over the hight
+    Raviens,
+    Clifs,
+    Fiords,
+    /// chunk outlines with the chunk coordinate
+    ChunkBorders,
+}
+
+impl ViewMode {
+    const ALL: [ViewMode; 9] = [
+        ViewMode::Textured,
+        ViewMode::Wireframe,
+        ViewMode::Height,
+        ViewMode::Slope,
+        ViewMode::Normals,
+        ViewMode::Raviens,
+        ViewMode::Clifs,
+        ViewMode::Fiords,
+        ViewMode::ChunkBorders,
+    ];
+
+    fn next(self) -> ViewMode {
+        let i = ViewMode::ALL.iter().position(|m| *m == self).unwrap();
+        ViewMode::ALL[(i + 1) % ViewMode::ALL.len()]
+    }
+}
+
+/// what a chunk's render entity is showing, and the entitys spawned to show it
+#[derive(Component)]
+pub struct Shown {
+    mode: ViewMode,
+    extras: Vec<Entity>,
+}
+
+/// a chunk coordinate drawn over the chunk
+#[derive(Component)]
+pub struct ChunkLabel {
+    /// the render entity of the chunk
+    chunk: Entity,
+    anchor: Vec3,
+}
+
+/// assets shared by all debug views
+#[derive(Resource)]
+pub struct DebugAssets {
+    font: Handle<Font>,
+    lines: Handle<StandardMaterial>,
+}
+
+/// add the required resources
+pub fn init(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ViewMode::default());
+    commands.insert_resource(DebugAssets {
+        font: asset_server.load(ASSETS_FONT),
+        lines: materials.add(StandardMaterial {
+            base_color: Color::YELLOW,
+            unlit: true,
+            ..Default::default()
+        }),
+    });
+}
+
+/// step to the next view mode
+pub fn cycle(
+    state: Res<ActionState>,
+    mut mode: ResMut<ViewMode>,
+) {
+    if state.just_pressed(Action::CycleView) {
+        *mode = mode.next();
+        println!("view mode {:?}", *mode);
+    }
+}
+
+/// show every chunk in the current view mode, this also catches chunks generated after the mode changed
+#[allow(clippy::too_many_arguments)]
+pub fn apply(
+    mut commands: Commands,
+    mode: Res<ViewMode>,
+    world: Res<WorldSeed>,
+    assets: Res<DebugAssets>,
+    maps: Query<&Map>,
+    shown: Query<&Shown>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for map in maps.iter() {
+        let (render, hightmap) = match (map.render, &map.hightmap) {
+            (Some(r), Some(h)) => (r, h),
+            _ => continue
+        };
+        let old = shown.get(render).ok();
+        if old.map(|s| s.mode) == Some(*mode) {
+            continue;
+        }
+        for e in old.iter().flat_map(|s| s.extras.iter()) {
+            commands.entity(*e).despawn_recursive();
+        }
+
+        let mut r = commands.entity(render);
+        let mut extras = Vec::new();
+        match debug_texture(*mode, hightmap, map::world_chunk(map.seed, world.0)) {
+            Some(tex) => {
+                r.insert(materials.add(StandardMaterial {
+                    base_color_texture: Some(images.add(tex)),
+                    unlit: true,
+                    ..Default::default()
+                }));
+                r.remove::<Handle<TerrainMaterial>>();
+            }
+            None => if let Some(m) = &map.material {
+                r.insert(m.clone());
+                r.remove::<Handle<StandardMaterial>>();
+            }
+        }
+        if *mode == ViewMode::Wireframe {
+            r.insert(Wireframe);
+        } else {
+            r.remove::<Wireframe>();
+        }
+        if *mode == ViewMode::ChunkBorders {
+            r.with_children(|c| {
+                extras.push(c.spawn(PbrBundle {
+                    mesh: meshes.add(border_mesh(hightmap)),
+                    material: assets.lines.clone(),
+                    ..Default::default()
+                }).id());
+            });
+            let s = map::getchunksize();
+            let middle = map.transform.translation + Vec3::new(s / 2.0, 0.0, s / 2.0);
+            let h = hightmap[CHUNK_SIZE / 2 + CHUNK_SIZE / 2 * CHUNK_SIZE] * VOXEL_SCALE;
+            extras.push(commands.spawn((
+                TextBundle::from_section(
+                    format!("{}, {}", map.seed.0, map.seed.1),
+                    TextStyle {
+                        font: assets.font.clone(),
+                        font_size: 20.0,
+                        color: Color::YELLOW,
+                    },
+                ).with_style(Style {
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                }),
+                ChunkLabel {
+                    chunk: render,
+                    anchor: middle + Vec3::Y * (h + 1.0),
+                },
+            )).id());
+        }
+        commands.entity(render).insert(Shown {mode: *mode, extras});
+    }
+}
+
+/// keep labels over their chunks, and remove labels of unloaded chunks
+pub fn place_labels(
+    mut commands: Commands,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    shown: Query<&Shown>,
+    mut labels: Query<(Entity, &ChunkLabel, &mut Style, &mut Visibility)>,
+) {
+    let (camera, eye) = match cameras.iter().next() {
+        Some(c) => c,
+        None => return
+    };
+    for (e, label, mut style, mut visibility) in labels.iter_mut() {
+        if shown.get(label.chunk).is_err() {
+            commands.entity(e).despawn();
+            continue;
+        }
+        match camera.world_to_viewport(eye, label.anchor) {
+            Some(p) => {
+                style.position.left = Val::Px(p.x);
+                style.position.bottom = Val::Px(p.y);
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+/// a line loop arround the edge of a chunk, just above the ground
+fn border_mesh(hightmap: &ChunkData<f32>) -> Mesh {
+    let last = CHUNK_SIZE - 1;
+    // walk the edge in order
+    let edge: Vec<(usize, usize)> = (0..last).map(|i| (i, 0))
+        .chain((0..last).map(|i| (last, i)))
+        .chain((0..last).map(|i| (last - i, last)))
+        .chain((0..last).map(|i| (0, last - i)))
+        .collect();
+    let position: Vec<[f32; 3]> = edge.iter()
+        .map(|(x, z)| [
+            *x as f32 * VOXEL_SCALE,
+            (hightmap[x + z * CHUNK_SIZE] + 0.2) * VOXEL_SCALE,
+            *z as f32 * VOXEL_SCALE,
+        ])
+        .collect();
+    let normals = vec![[0.0, 1.0, 0.0]; position.len()];
+    let indeces: Vec<u32> = (0..edge.len() as u32)
+        .flat_map(|i| [i, (i + 1) % edge.len() as u32])
+        .collect();
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_indices(Some(bevy::render::mesh::Indices::U32(indeces)));
+    mesh
+}
+
+/// the colour for a hight, blue under the sea then green, brown and white going up
+fn height_colour(h: f32) -> [f32; 3] {
+    if h < 0.0 {
+        mix([0.0, 0.0, 0.5], [0.2, 0.4, 1.0], (h + 10.0) / 10.0)
+    } else if h < 15.0 {
+        mix([0.1, 0.5, 0.1], [0.6, 0.5, 0.3], h / 15.0)
+    } else {
+        mix([0.6, 0.5, 0.3], [1.0, 1.0, 1.0], (h - 15.0) / 15.0)
+    }
+}
+
+/// the normal at a point from its neighbours, in mesh space
+fn normal(hightmap: &ChunkData<f32>, x: usize, z: usize) -> Vec3 {
+    let h = |x: usize, z: usize| hightmap[x.min(CHUNK_SIZE - 1) + z.min(CHUNK_SIZE - 1) * CHUNK_SIZE];
+    let dx = (h(x + 1, z) - h(x.saturating_sub(1), z)) / 2.0;
+    let dz = (h(x, z + 1) - h(x, z.saturating_sub(1))) / 2.0;
+    Vec3::new(-dx, 1.0, -dz).normalize()
+}
+
+/// colour a chunk for a debug view, one pixel per point, None for views that use the normal texture
+/// seed is the chunk seed after world_chunk
+pub fn debug_texture(mode: ViewMode, hightmap: &ChunkData<f32>, seed: (f32, f32)) -> Option<Image> {
+    if matches!(mode, ViewMode::Textured | ViewMode::Wireframe | ViewMode::ChunkBorders) {
+        return None;
+    }
+    let region: fn(&reg::Regdata) -> f32 = match mode {
+        ViewMode::Raviens => |r| r.raviens,
+        ViewMode::Clifs => |r| r.clifs,
+        ViewMode::Fiords => |r| r.fiords,
+        _ => |_| 0.0,
+    };
+    let slope = match mode {
+        ViewMode::Slope => Some(map::genslope(hightmap)),
+        _ => None
+    };
+    let regs = match mode {
+        ViewMode::Raviens | ViewMode::Clifs | ViewMode::Fiords => Some(map::genchunkregs(seed)),
+        _ => None
+    };
+    let data: Vec<u8> = (0..map::CHUNK_SQSIZE).flat_map(|i| {
+        let x = i % CHUNK_SIZE;
+        let z = i / CHUNK_SIZE;
+        let c = match mode {
+            ViewMode::Height => height_colour(hightmap[i]),
+            ViewMode::Slope => {
+                // red where the texture uses stone
+                let s = slope.as_ref().unwrap()[i];
+                if s > map::STONE_SLOPE {[1.0, 0.0, 0.0]} else {mix([0.0, 0.0, 0.0], [1.0, 1.0, 0.0], s / map::STONE_SLOPE)}
+            }
+            ViewMode::Normals => {
+                let n = normal(hightmap, x, z) * 0.5 + 0.5;
+                [n.x, n.y, n.z]
+            }
+            _ => {
+                let f = map::blend_reg(regs.as_ref().unwrap(), x as f32 / CHUNK_SIZE as f32, z as f32 / CHUNK_SIZE as f32, region);
+                let g = (hightmap[i] + 10.0) / 40.0;
+                mix([g, g, g], [1.0, 0.0, 0.0], f)
+            }
+        };
+        [(c[0] * 255.0) as u8, (c[1] * 255.0) as u8, (c[2] * 255.0) as u8, 255]
+    }).collect();
+
+    Some(Image::new(
+        Extent3d {
+            width: CHUNK_SIZE as u32,
+            height: CHUNK_SIZE as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+    ))
+}
@@ -0,0 +1,239 @@
+//! real places from digital elevation models, ESRI ascii grids (.asc) and SRTM tiles (.hgt)
+//! a dem is resampled to one sample per point and turned into a Heightmap, so it goes through the same
+//! import path as heightmap files and chunks over it get the usual slope, texture and mesh.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::str::FromStr;
+use crate::heightmap::Heightmap;
+use crate::map;
+
+/// metres per degree of latitude
+const METRES_PER_DEGREE: f32 = 111_320.0;
+/// void samples in SRTM tiles
+const HGT_VOID: i16 = -32768;
+
+/// elevations in metres, the first row is the northern edge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dem {
+    pub width: usize,
+    pub height: usize,
+    /// metres between samples, east-west and north-south
+    pub cellsize: (f32, f32),
+    /// None for missing samples
+    pub data: Vec<Option<f32>>,
+}
+
+/// what the cellsize of an ascii grid is measured in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Units {
+    Metres,
+    /// a geographic grid, cells are converted to metres at the latitude of the middle of the grid
+    Degrees,
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Units, String> {
+        match s {
+            "metres" | "meters" => Ok(Units::Metres),
+            "degrees" => Ok(Units::Degrees),
+            _ => Err(format!("unknown units {}, expected metres or degrees", s)),
+        }
+    }
+}
+
+/// the units of an ascii grid from the .prj file next to it, a geographic coordinate system is in degrees
+/// grids without one are taken to be in metres
+fn prj_units(prj: &Path) -> Units {
+    match std::fs::read_to_string(prj) {
+        Ok(wkt) if wkt.contains("GEOGCS") && !wkt.contains("PROJCS") => Units::Degrees,
+        _ => Units::Metres,
+    }
+}
+
+/// how a dem becomes hights
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DemOptions {
+    /// metres covered by a point, None for one sample per point
+    pub metres_per_point: Option<f32>,
+    /// vertical exaggeration, 1.0 keeps hights in proportion
+    pub exaggeration: f32,
+    /// how much of the procedural detail octaves to add on top, in points
+    pub detail: f32,
+    /// the units of an ascii grid's cellsize, None to read them from its .prj file
+    pub units: Option<Units>,
+}
+
+impl Default for DemOptions {
+    fn default() -> DemOptions {
+        DemOptions {
+            metres_per_point: None,
+            exaggeration: 1.0,
+            detail: 0.0,
+            units: None,
+        }
+    }
+}
+
+/// read an ESRI ascii grid, the header does not say what the cellsize is in so it is given
+pub fn read_asc(text: &str, units: Units) -> Result<Dem, String> {
+    let mut words = text.split_whitespace().peekable();
+    let (mut ncols, mut nrows, mut cellsize, mut lat, mut nodata) = (None, None, None, 0.0, None);
+    // the header is key value pairs until the first number
+    while let Some(key) = words.peek().filter(|w| w.parse::<f32>().is_err()).map(|w| w.to_lowercase()) {
+        words.next();
+        let value: f32 = words.next().and_then(|v| v.parse().ok()).ok_or(format!("{} needs a number", key))?;
+        match key.as_str() {
+            "ncols" => ncols = Some(value as usize),
+            "nrows" => nrows = Some(value as usize),
+            "cellsize" => cellsize = Some(value),
+            "yllcorner" | "yllcenter" => lat = value,
+            "nodata_value" => nodata = Some(value),
+            "xllcorner" | "xllcenter" => (),
+            _ => return Err(format!("unknown header {}", key)),
+        }
+    }
+    let (width, height, cellsize) = match (ncols, nrows, cellsize) {
+        (Some(w), Some(h), Some(c)) => (w, h, c),
+        _ => return Err("missing ncols, nrows or cellsize".to_string()),
+    };
+    // negative sizes are 0 by now
+    if width < 2 || height < 2 {
+        return Err(format!("a {}x{} grid is too small", width, height));
+    }
+    let cellsize = match units {
+        Units::Degrees => {
+            let middle = (lat + cellsize * height as f32 / 2.0).to_radians();
+            (cellsize * METRES_PER_DEGREE * middle.cos(), cellsize * METRES_PER_DEGREE)
+        }
+        Units::Metres => (cellsize, cellsize),
+    };
+    let data = words.map(|w| {
+        let v: f32 = w.parse().map_err(|_| format!("bad sample {}", w))?;
+        Ok((Some(v) != nodata).then_some(v))
+    }).collect::<Result<Vec<_>, String>>()?;
+    if data.len() != width * height {
+        return Err(format!("expected {}x{} samples but found {}", width, height, data.len()));
+    }
+    Ok(Dem {width, height, cellsize, data})
+}
+
+/// read an SRTM tile, big endian i16 covering one degree, name is the file name like N45E006.hgt for its latitude
+pub fn read_hgt(bytes: &[u8], name: &str) -> Result<Dem, String> {
+    let size = ((bytes.len() / 2) as f32).sqrt() as usize;
+    if size < 2 || size * size * 2 != bytes.len() {
+        return Err(format!("{} is not a square hgt tile", name));
+    }
+    let lat: f32 = name.get(1..3).and_then(|l| l.parse().ok()).ok_or(format!("cant read the latitude from {}", name))?;
+    let lat = if name.starts_with(['S', 's']) {-lat} else {lat};
+    let degrees = 1.0 / (size - 1) as f32;
+    let middle = (lat + 0.5).to_radians();
+    Ok(Dem {
+        width: size,
+        height: size,
+        cellsize: (degrees * METRES_PER_DEGREE * middle.cos(), degrees * METRES_PER_DEGREE),
+        data: bytes.chunks_exact(2).map(|b| {
+            let v = i16::from_be_bytes([b[0], b[1]]);
+            (v != HGT_VOID).then_some(v as f32)
+        }).collect(),
+    })
+}
+
+impl Dem {
+    /// the samples next to a sample
+    fn neighbours(&self, i: usize) -> impl Iterator<Item = usize> {
+        let (w, h) = (self.width, self.height);
+        let (x, z) = (i % w, i / w);
+        IntoIterator::into_iter([(x.wrapping_sub(1), z), (x + 1, z), (x, z.wrapping_sub(1)), (x, z + 1)])
+            .filter(move |(x, z)| *x < w && *z < h)
+            .map(move |(x, z)| x + z * w)
+    }
+
+    /// fill missing samples from their neighbours
+    /// a flood fill outwards from the known samples, each missing sample is the average of its neighbours known when it is reached
+    fn filled(&self) -> Vec<f32> {
+        let mut data = self.data.clone();
+        let mut queue: VecDeque<usize> = (0..data.len()).filter(|i| data[*i].is_some()).collect();
+        while let Some(i) = queue.pop_front() {
+            for n in self.neighbours(i) {
+                if data[n].is_some() {
+                    continue;
+                }
+                let known: Vec<f32> = self.neighbours(n).filter_map(|k| data[k]).collect();
+                data[n] = Some(known.iter().sum::<f32>() / known.len() as f32);
+                queue.push_back(n);
+            }
+        }
+        // a grid with no samples at all
+        data.into_iter().map(|v| v.unwrap_or(0.0)).collect()
+    }
+
+    /// the hights, starting at the world origin with north towards -z
+    /// sea level (0 m) is at map::SEA_LEVEL and hights are in points
+    pub fn to_heightmap(&self, options: &DemOptions) -> Heightmap {
+        let metres = options.metres_per_point.unwrap_or(self.cellsize.1);
+        let raw = Heightmap {
+            width: self.width,
+            height: self.height,
+            origin: (0, 0),
+            data: self.filled().into_iter().map(|m| map::SEA_LEVEL + m / metres * options.exaggeration).collect(),
+        };
+        let mut hm = raw.resample((self.cellsize.0 / metres, self.cellsize.1 / metres));
+        if options.detail != 0.0 {
+            // the finest octaves of the procedural terrain, so close up it is not smooth between samples
+            for (i, h) in hm.data.iter_mut().enumerate() {
+                let (x, z) = ((i % hm.width) as i32 + hm.origin.0, (i / hm.width) as i32 + hm.origin.1);
+                *h += map::get_detail(x as f32, z as f32) * options.detail;
+            }
+        }
+        hm
+    }
+}
+
+/// read any heightmap or dem, by its extension
+pub fn import(path: &Path, options: &DemOptions) -> Result<Heightmap, String> {
+    let err = |e: std::io::Error| format!("cant read {}: {}", path.display(), e);
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("asc") => {
+            let units = options.units.unwrap_or_else(|| prj_units(&path.with_extension("prj")));
+            Ok(read_asc(&std::fs::read_to_string(path).map_err(err)?, units)?.to_heightmap(options))
+        }
+        Some("hgt") => Ok(read_hgt(&std::fs::read(path).map_err(err)?, name)?.to_heightmap(options)),
+        _ => Heightmap::read(path),
+    }
+}
+
+#[test]
+fn dems_are_read_and_resampled() {
+    let asc = "ncols 3\nnrows 2\nxllcorner 0\nyllcorner 0\ncellsize 30\nNODATA_value -9999\n0 30 60\n-9999 30 60\n";
+    let dem = read_asc(asc, Units::Metres).unwrap();
+    assert_eq!(dem.data[3], None);
+    // one sample every 10 m, so each cell becomes 3 points and 30 m of hight is 3 points
+    let hm = dem.to_heightmap(&DemOptions {metres_per_point: Some(10.0), ..Default::default()});
+    assert_eq!((hm.width, hm.height), (7, 4));
+    assert!((hm.data[3] - (map::SEA_LEVEL + 3.0)).abs() < 1e-4);
+    // the hole is filled from its neighbours
+    assert!((hm.data[3 * 7] - map::SEA_LEVEL - 1.5).abs() < 1e-4);
+    // grids too small to resample are refused rather than panicking later
+    assert!(read_asc("ncols 0\nnrows 0\ncellsize 30\n", Units::Metres).is_err());
+    assert!(read_asc("ncols -3\nnrows 2\ncellsize 30\n0 0 0 0 0 0\n", Units::Metres).is_err());
+
+    let mut hgt = Vec::new();
+    for v in [100i16, 200, HGT_VOID, 400] {
+        hgt.extend(v.to_be_bytes());
+    }
+    let dem = read_hgt(&hgt, "N60E010.hgt").unwrap();
+    // a degree of longitude is half as long at 60 north
+    assert!((dem.cellsize.0 / dem.cellsize.1 - 0.5).abs() < 0.01);
+    assert_eq!(dem.filled()[2], 250.0);
+    // coarse geographic grids are still degrees
+    let dem = read_asc(&asc.replace("cellsize 30", "cellsize 0.25"), Units::Degrees).unwrap();
+    assert!((dem.cellsize.1 - 0.25 * METRES_PER_DEGREE).abs() < 1.0);
+    // a big hole is filled all the way across
+    let mut dem = Dem {width: 500, height: 500, cellsize: (1.0, 1.0), data: vec![None; 500 * 500]};
+    dem.data[0] = Some(7.0);
+    assert!(dem.filled().iter().all(|h| *h == 7.0));
+}
@@ -0,0 +1,394 @@
+//! generating chunks without bevy running and writing them to disk, used by the terrain-gen tool
+//! everything here is deterministic, the same world and chunk always write the same bytes.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::render::texture::{CompressedImageFormats, ImageType};
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+use crate::cave::{self, Volume};
+use crate::heightmap::Heightmap;
+use crate::map::{self, ChunkData, CHUNK_SIZE, PIXELS_PER_POINT, VOXEL_SCALE};
+use crate::poi::Pois;
+
+/// a generated chunk, the same as chunk::generate_maps makes without roads
+pub struct Chunk {
+    /// the chunk seed, before world_chunk
+    pub seed: (i32, i32),
+    pub hightmap: ChunkData<f32>,
+    pub slope: ChunkData<f32>,
+    pub volume: Option<Volume>,
+}
+
+impl Chunk {
+    /// generate a chunk, pois is only a cache and can be shared between chunks of the same world
+    /// chunks covered by an imported heightmap take their hights from it
+    pub fn generate(world: u32, seed: (i32, i32), imported: Option<&Heightmap>, pois: &mut Pois) -> Chunk {
+        Chunk::generate_with(world, seed, imported, pois, |s| map::genhightmap(s, world))
+    }
+
+    /// generate a chunk with the procedural hights from base, for hights generated ahead of time
+    pub fn generate_with(
+        world: u32,
+        seed: (i32, i32),
+        imported: Option<&Heightmap>,
+        pois: &mut Pois,
+        base: impl FnOnce((f32, f32)) -> ChunkData<f32>,
+    ) -> Chunk {
+        let s = (seed.0 as f32, seed.1 as f32);
+        let hightmap = match imported.and_then(|i| i.chunk(s)) {
+            Some(h) => h,
+            None => {
+                let mut h = base(s);
+                pois.flatten(world, &mut h, s);
+                h
+            }
+        };
+        let slope = map::genslope(&hightmap);
+        let volume = cave::genvolume(&hightmap, map::world_chunk(s, world));
+        Chunk {seed, hightmap, slope, volume}
+    }
+
+    /// the mesh the game would draw
+    pub fn mesh(&self) -> Mesh {
+        match &self.volume {
+            Some(v) => v.mesh(None),
+            None => map::chunktomesh(&self.hightmap),
+        }
+    }
+
+    /// the texture from the cpu texturing path (chunktotexture)
+    pub fn texture(&self, world: u32, tiles: &Tiles) -> Image {
+        let seed = map::world_chunk((self.seed.0 as f32, self.seed.1 as f32), world);
+        map::chunktotexture(
+            &self.hightmap,
+            &self.slope,
+            &map::genchunkregs(seed),
+            &tiles.grass,
+            &tiles.water,
+            &tiles.sand,
+            &tiles.snow,
+            &tiles.stone,
+            seed
+        )
+    }
+}
+
+/// the tile textures chunktotexture needs, read straight from the assets folder
+pub struct Tiles {
+    grass: Image,
+    water: Image,
+    sand: Image,
+    snow: Image,
+    stone: Image,
+}
+
+impl Tiles {
+    pub fn load(assets: &Path) -> Result<Tiles, String> {
+        let load = |name: &str| -> Result<Image, String> {
+            let bytes = fs::read(assets.join(name)).map_err(|e| format!("cant read {}: {}", name, e))?;
+            Image::from_buffer(&bytes, ImageType::Extension("png"), CompressedImageFormats::NONE, true)
+                .map_err(|e| format!("cant load {}: {}", name, e))
+        };
+        Ok(Tiles {
+            grass: load(map::ASSETS_GRASS)?,
+            water: load(map::ASSETS_WATER)?,
+            sand: load(map::ASSETS_SAND)?,
+            snow: load(map::ASSETS_SNOW)?,
+            stone: load(map::ASSETS_STONE)?,
+        })
+    }
+}
+
+/// write values as raw little endian f32
+pub fn write_f32(path: &Path, data: &[f32]) -> Result<(), String> {
+    let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+    fs::write(path, bytes).map_err(|e| format!("cant write {}: {}", path.display(), e))
+}
+
+/// write an image as a png
+pub fn write_png(path: &Path, image: Image) -> Result<(), String> {
+    image.try_into_dynamic()
+        .map_err(|e| e.to_string())?
+        .save(path)
+        .map_err(|e| format!("cant write {}: {}", path.display(), e))
+}
+
+/// write a triangle list mesh as a wavefront obj, with its normals and uvs if it has them
+pub fn write_obj(out: &mut impl Write, mesh: &Mesh) -> Result<(), String> {
+    let attribute = |id| match mesh.attribute(id) {
+        Some(VertexAttributeValues::Float32x3(v)) => v.iter().map(|p| p.to_vec()).collect(),
+        Some(VertexAttributeValues::Float32x2(v)) => v.iter().map(|p| p.to_vec()).collect(),
+        _ => Vec::new(),
+    };
+    let positions: Vec<Vec<f32>> = attribute(Mesh::ATTRIBUTE_POSITION);
+    let normals: Vec<Vec<f32>> = attribute(Mesh::ATTRIBUTE_NORMAL);
+    let uvs: Vec<Vec<f32>> = attribute(Mesh::ATTRIBUTE_UV_0);
+    let indices: Vec<u32> = match mesh.indices() {
+        Some(Indices::U32(i)) => i.clone(),
+        Some(Indices::U16(i)) => i.iter().map(|i| *i as u32).collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let mut w = |s: String| out.write_all(s.as_bytes()).map_err(|e| e.to_string());
+    for p in positions.iter() {
+        w(format!("v {} {} {}\n", p[0], p[1], p[2]))?;
+    }
+    for n in normals.iter() {
+        w(format!("vn {} {} {}\n", n[0], n[1], n[2]))?;
+    }
+    for t in uvs.iter() {
+        w(format!("vt {} {}\n", t[0], t[1]))?;
+    }
+    // obj counts from 1
+    let vertex = |i: u32| match (uvs.is_empty(), normals.is_empty()) {
+        (true, true) => format!("{}", i + 1),
+        (false, true) => format!("{}/{}", i + 1, i + 1),
+        (true, false) => format!("{}//{}", i + 1, i + 1),
+        (false, false) => format!("{}/{}/{}", i + 1, i + 1, i + 1),
+    };
+    for t in indices.chunks(3) {
+        w(format!("f {} {} {}\n", vertex(t[0]), vertex(t[1]), vertex(t[2])))?;
+    }
+    Ok(())
+}
+
+/// join the textures of a rectangle of chunks, from and to are inclusive chunk seeds
+/// like Heightmap::stitch, chunks overlap by their edge points, so it lines up with the uvs of Heightmap::mesh
+pub fn stitch_textures(from: (i32, i32), to: (i32, i32), mut texture: impl FnMut((i32, i32)) -> Image) -> Image {
+    let n = (CHUNK_SIZE - 1) * PIXELS_PER_POINT;
+    let width = (to.0 - from.0 + 1) as usize * n + PIXELS_PER_POINT;
+    let height = (to.1 - from.1 + 1) as usize * n + PIXELS_PER_POINT;
+    let mut data = vec![0; width * height * 4];
+    for cz in from.1..=to.1 {
+        for cx in from.0..=to.0 {
+            let t = texture((cx, cz));
+            let size = t.texture_descriptor.size.width as usize;
+            let (ox, oz) = ((cx - from.0) as usize * n, (cz - from.1) as usize * n);
+            for row in 0..size {
+                let start = (ox + (oz + row) * width) * 4;
+                data[start..start + size * 4].copy_from_slice(&t.data[row * size * 4..(row + 1) * size * 4]);
+            }
+        }
+    }
+    Image::new(
+        Extent3d {width: width as u32, height: height as u32, depth_or_array_layers: 1},
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// the floats of a mesh attribute, empty if the mesh does not have it
+fn floats<const N: usize>(mesh: &Mesh, id: bevy::render::mesh::MeshVertexAttribute) -> Vec<[f32; N]> {
+    match mesh.attribute(id) {
+        Some(VertexAttributeValues::Float32x3(v)) => v.iter().map(|p| std::array::from_fn(|i| p[i])).collect(),
+        Some(VertexAttributeValues::Float32x2(v)) => v.iter().map(|p| std::array::from_fn(|i| p[i])).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// write a mesh as binary gltf (glb), with the texture embedded as the base colour of its material
+pub fn write_glb(out: &mut impl Write, mesh: &Mesh, texture: Option<Image>) -> Result<(), String> {
+    let positions: Vec<[f32; 3]> = floats(mesh, Mesh::ATTRIBUTE_POSITION);
+    let normals: Vec<[f32; 3]> = floats(mesh, Mesh::ATTRIBUTE_NORMAL);
+    let uvs: Vec<[f32; 2]> = floats(mesh, Mesh::ATTRIBUTE_UV_0);
+    let indices: Vec<u32> = match mesh.indices() {
+        Some(Indices::U32(i)) => i.clone(),
+        Some(Indices::U16(i)) => i.iter().map(|i| *i as u32).collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    let png = match texture {
+        Some(t) => {
+            let mut png = Cursor::new(Vec::new());
+            t.try_into_dynamic().map_err(|e| e.to_string())?
+                .write_to(&mut png, image::ImageOutputFormat::Png)
+                .map_err(|e| e.to_string())?;
+            Some(png.into_inner())
+        }
+        None => None,
+    };
+
+    // everything goes in one buffer, each view starting on a multiple of 4 bytes
+    let mut bin: Vec<u8> = Vec::new();
+    let mut views: Vec<String> = Vec::new();
+    let mut view = |bin: &mut Vec<u8>, bytes: Vec<u8>, target: Option<u32>| -> usize {
+        let target = target.map_or(String::new(), |t| format!(",\"target\":{}", t));
+        views.push(format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{}{}}}", bin.len(), bytes.len(), target));
+        bin.extend(bytes);
+        bin.resize(bin.len().div_ceil(4) * 4, 0);
+        views.len() - 1
+    };
+    let bytes = |v: Vec<f32>| v.iter().flat_map(|f| f.to_le_bytes()).collect::<Vec<u8>>();
+    // 34962 is ARRAY_BUFFER and 34963 ELEMENT_ARRAY_BUFFER, 5126 is FLOAT and 5125 UNSIGNED_INT
+    let mut accessors: Vec<String> = Vec::new();
+    let mut attributes: Vec<String> = Vec::new();
+    let (lo, hi) = positions.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(lo, hi), p| {
+        (std::array::from_fn(|i| lo[i].min(p[i])), std::array::from_fn(|i| hi[i].max(p[i])))
+    });
+    let v = view(&mut bin, bytes(positions.iter().flatten().cloned().collect()), Some(34962));
+    accessors.push(format!(
+        "{{\"bufferView\":{},\"componentType\":5126,\"count\":{},\"type\":\"VEC3\",\"min\":[{},{},{}],\"max\":[{},{},{}]}}",
+        v, positions.len(), lo[0], lo[1], lo[2], hi[0], hi[1], hi[2]
+    ));
+    attributes.push(format!("\"POSITION\":{}", accessors.len() - 1));
+    if !normals.is_empty() {
+        let v = view(&mut bin, bytes(normals.iter().flatten().cloned().collect()), Some(34962));
+        accessors.push(format!("{{\"bufferView\":{},\"componentType\":5126,\"count\":{},\"type\":\"VEC3\"}}", v, normals.len()));
+        attributes.push(format!("\"NORMAL\":{}", accessors.len() - 1));
+    }
+    if !uvs.is_empty() {
+        let v = view(&mut bin, bytes(uvs.iter().flatten().cloned().collect()), Some(34962));
+        accessors.push(format!("{{\"bufferView\":{},\"componentType\":5126,\"count\":{},\"type\":\"VEC2\"}}", v, uvs.len()));
+        attributes.push(format!("\"TEXCOORD_0\":{}", accessors.len() - 1));
+    }
+    let v = view(&mut bin, indices.iter().flat_map(|i| i.to_le_bytes()).collect(), Some(34963));
+    accessors.push(format!("{{\"bufferView\":{},\"componentType\":5125,\"count\":{},\"type\":\"SCALAR\"}}", v, indices.len()));
+    let indices_accessor = accessors.len() - 1;
+
+    let (material, textures) = match png {
+        Some(png) if !uvs.is_empty() => {
+            let v = view(&mut bin, png, None);
+            (
+                "{\"pbrMetallicRoughness\":{\"baseColorTexture\":{\"index\":0},\"metallicFactor\":0,\"roughnessFactor\":1}}".to_string(),
+                // 9728 is NEAREST, the textures are pixel art
+                format!(
+                    ",\"images\":[{{\"bufferView\":{},\"mimeType\":\"image/png\"}}],\"samplers\":[{{\"magFilter\":9728,\"minFilter\":9728}}],\"textures\":[{{\"source\":0,\"sampler\":0}}]",
+                    v
+                ),
+            )
+        }
+        _ => ("{\"pbrMetallicRoughness\":{\"metallicFactor\":0,\"roughnessFactor\":1}}".to_string(), String::new()),
+    };
+    let mut json = format!(
+        "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"terrain\"}},\"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],\
+        \"meshes\":[{{\"primitives\":[{{\"attributes\":{{{}}},\"indices\":{},\"material\":0}}]}}],\"materials\":[{}]{},\
+        \"accessors\":[{}],\"bufferViews\":[{}],\"buffers\":[{{\"byteLength\":{}}}]}}",
+        attributes.join(","), indices_accessor, material, textures, accessors.join(","), views.join(","), bin.len()
+    ).into_bytes();
+    json.resize(json.len().div_ceil(4) * 4, b' ');
+
+    let mut glb: Vec<u8> = Vec::new();
+    glb.extend(b"glTF");
+    glb.extend(2u32.to_le_bytes());
+    glb.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+    glb.extend((json.len() as u32).to_le_bytes());
+    glb.extend(b"JSON");
+    glb.extend(json);
+    glb.extend((bin.len() as u32).to_le_bytes());
+    glb.extend(b"BIN\0");
+    glb.extend(bin);
+    out.write_all(&glb).map_err(|e| e.to_string())
+}
+
+/// write a heightmap as a closed binary stl for 3d printing, in world units
+/// walls go down from every edge to a flat bottom at base (points), which should be below the lowest point
+pub fn write_stl(out: &mut impl Write, heightmap: &Heightmap, base: f32) -> Result<(), String> {
+    let (w, h) = (heightmap.width, heightmap.height);
+    let top = |x: usize, z: usize| Vec3::new(x as f32, heightmap.data[x + z * w], z as f32) * VOXEL_SCALE;
+    let bottom = |x: usize, z: usize| Vec3::new(x as f32, base, z as f32) * VOXEL_SCALE;
+    let mut triangles: Vec<[Vec3; 3]> = Vec::new();
+    // wound so the normal points outward, the same way as chunktomesh for the top
+    let mut add = |a: Vec3, b: Vec3, c: Vec3, outward: Vec3| {
+        if (b - a).cross(c - a).dot(outward) < 0.0 {
+            triangles.push([a, c, b]);
+        } else {
+            triangles.push([a, b, c]);
+        }
+    };
+    for z in 0..h - 1 {
+        for x in 0..w - 1 {
+            add(top(x + 1, z), top(x, z), top(x, z + 1), Vec3::Y);
+            add(top(x + 1, z), top(x, z + 1), top(x + 1, z + 1), Vec3::Y);
+        }
+    }
+    // the edge points going round the heightmap, with which way is out along each side
+    let mut edge: Vec<((usize, usize), Vec3)> = Vec::new();
+    edge.extend((0..w - 1).map(|x| ((x, 0), -Vec3::Z)));
+    edge.extend((0..h - 1).map(|z| ((w - 1, z), Vec3::X)));
+    edge.extend((1..w).rev().map(|x| ((x, h - 1), Vec3::Z)));
+    edge.extend((1..h).rev().map(|z| ((0, z), -Vec3::X)));
+    let centre = Vec3::new((w - 1) as f32 / 2.0, base, (h - 1) as f32 / 2.0) * VOXEL_SCALE;
+    for i in 0..edge.len() {
+        let ((x0, z0), outward) = edge[i];
+        let ((x1, z1), _) = edge[(i + 1) % edge.len()];
+        add(top(x0, z0), bottom(x0, z0), bottom(x1, z1), outward);
+        add(top(x0, z0), bottom(x1, z1), top(x1, z1), outward);
+        // the bottom is a fan from the middle, so it shares every edge with the walls
+        add(centre, bottom(x0, z0), bottom(x1, z1), -Vec3::Y);
+    }
+
+    let mut stl: Vec<u8> = Vec::with_capacity(84 + triangles.len() * 50);
+    let mut header = b"terrain".to_vec();
+    header.resize(80, 0);
+    stl.extend(header);
+    stl.extend((triangles.len() as u32).to_le_bytes());
+    for t in triangles.iter() {
+        let n = (t[1] - t[0]).cross(t[2] - t[0]).normalize_or_zero();
+        for v in [n, t[0], t[1], t[2]] {
+            stl.extend(v.to_array().iter().flat_map(|f| f.to_le_bytes()));
+        }
+        stl.extend([0, 0]);
+    }
+    out.write_all(&stl).map_err(|e| e.to_string())
+}
+
+/// write to a file with one of the writers above
+pub fn write_file(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> Result<(), String>) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("cant write {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    write(&mut out).map_err(|e| format!("cant write {}: {}", path.display(), e))?;
+    out.flush().map_err(|e| e.to_string())
+}
+
+#[test]
+fn obj_has_every_vertex_and_triangle() {
+    let mesh = map::chunktomesh(&Box::new([0.0_f32; map::CHUNK_SQSIZE]));
+    let mut out = Vec::new();
+    write_obj(&mut out, &mesh).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let count = |prefix: &str| text.lines().filter(|l| l.starts_with(prefix)).count();
+    assert_eq!(count("v "), map::CHUNK_SQSIZE);
+    assert_eq!(count("f "), (map::CHUNK_SIZE - 1) * (map::CHUNK_SIZE - 1) * 2);
+    let below = map::CHUNK_SIZE + 1;
+    assert!(text.contains(&format!("f 2/2/2 1/1/1 {}/{}/{}\n", below, below, below)));
+}
+
+#[test]
+fn stl_is_closed_and_glb_is_valid() {
+    let hm = Heightmap::stitch((0, 0), (0, 0), |_| map::genhightmap((0.0, 0.0), 5));
+    let mut stl = Vec::new();
+    write_stl(&mut stl, &hm, -10.0).unwrap();
+    let count = u32::from_le_bytes([stl[80], stl[81], stl[82], stl[83]]) as usize;
+    assert_eq!(stl.len(), 84 + count * 50);
+    // closed: every edge is used by exactly two triangles, once in each direction
+    let mut edges = std::collections::HashMap::new();
+    for t in stl[84..].chunks(50) {
+        let v: Vec<[u32; 3]> = (1..4).map(|i| std::array::from_fn(|j| {
+            let o = i * 12 + j * 4;
+            u32::from_le_bytes([t[o], t[o + 1], t[o + 2], t[o + 3]])
+        })).collect();
+        for k in 0..3 {
+            *edges.entry((v[k], v[(k + 1) % 3])).or_insert(0) += 1;
+        }
+    }
+    assert!(edges.iter().all(|(e, n)| *n == 1 && edges.get(&(e.1, e.0)) == Some(&1)));
+
+    let mut glb = Vec::new();
+    let mesh = hm.mesh();
+    write_glb(&mut glb, &mesh, None).unwrap();
+    assert_eq!(&glb[0..4], b"glTF");
+    assert_eq!(u32::from_le_bytes([glb[8], glb[9], glb[10], glb[11]]) as usize, glb.len());
+    // the json chunk parses and the accessors match the mesh
+    let json_len = u32::from_le_bytes([glb[12], glb[13], glb[14], glb[15]]) as usize;
+    assert_eq!(&glb[16..20], b"JSON");
+    let json: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+    let primitive = &json["meshes"][0]["primitives"][0];
+    let count = |accessor: &serde_json::Value| json["accessors"][accessor.as_u64().unwrap() as usize]["count"].as_u64().unwrap() as usize;
+    assert_eq!(count(&primitive["attributes"]["POSITION"]), mesh.count_vertices());
+    assert_eq!(count(&primitive["attributes"]["NORMAL"]), mesh.count_vertices());
+    assert_eq!(count(&primitive["indices"]), mesh.indices().unwrap().len());
+    assert_eq!(&glb[20 + json_len + 4..20 + json_len + 8], b"BIN\0");
+}
@@ -0,0 +1,286 @@
+//! heightmaps bigger than a chunk, for swapping hights with other tools
+//! they are written as 16 bit grayscale png, raw little endian u16 or raw f32, each with a ron sidecar saying how to read it,
+//! and can be read back in to generate chunks from instead of the procedural hights.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, Mesh};
+use bevy::render::render_resource::PrimitiveTopology;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use crate::map::{ChunkData, CHUNK_SIZE, VOXEL_SCALE};
+
+/// a png without a sidecar covers this many points from black to white
+pub const PNG_RANGE: f32 = 64.0;
+
+/// how the samples of a heightmap file are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    /// raw little endian f32
+    F32,
+    /// raw little endian u16
+    U16,
+    /// 16 bit grayscale png
+    Png16,
+}
+
+impl Format {
+    /// the format for a file name, from its extension
+    pub fn from_path(path: &Path) -> Result<Format, String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("f32") | Some("r32") => Ok(Format::F32),
+            Some("u16") | Some("r16") | Some("raw") => Ok(Format::U16),
+            Some("png") => Ok(Format::Png16),
+            _ => Err(format!("cant tell the heightmap format of {}", path.display())),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::F32 => "f32",
+            Format::U16 => "u16",
+            Format::Png16 => "png",
+        }
+    }
+}
+
+/// describes a heightmap file, written next to it with the extension changed to ron
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub width: usize,
+    pub height: usize,
+    pub format: Format,
+    /// hight in points = sample * scale + offset
+    pub scale: f32,
+    pub offset: f32,
+    /// world units between samples
+    pub spacing: f32,
+    /// the world point of the first sample
+    pub origin: (i32, i32),
+}
+
+/// the sidecar for a heightmap file
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension("ron")
+}
+
+/// hights on a grid of world points, in points, x changes fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    /// the world point of data[0]
+    pub origin: (i32, i32),
+    pub data: Vec<f32>,
+}
+
+impl Heightmap {
+    /// join a rectangle of chunks, from and to are inclusive chunk seeds
+    /// chunks share their edge points, so n chunks are n * (CHUNK_SIZE - 1) + 1 points wide
+    pub fn stitch(from: (i32, i32), to: (i32, i32), mut chunk: impl FnMut((i32, i32)) -> ChunkData<f32>) -> Heightmap {
+        let n = CHUNK_SIZE - 1;
+        let width = (to.0 - from.0 + 1) as usize * n + 1;
+        let height = (to.1 - from.1 + 1) as usize * n + 1;
+        let mut data = vec![0.0; width * height];
+        for cz in from.1..=to.1 {
+            for cx in from.0..=to.0 {
+                let c = chunk((cx, cz));
+                let (ox, oz) = ((cx - from.0) as usize * n, (cz - from.1) as usize * n);
+                for (i, h) in c.iter().enumerate() {
+                    data[ox + i % CHUNK_SIZE + (oz + i / CHUNK_SIZE) * width] = *h;
+                }
+            }
+        }
+        Heightmap {width, height, origin: (from.0 * n as i32, from.1 * n as i32), data}
+    }
+
+    /// the hight at a world point, clamped to the edge outside the heightmap
+    pub fn get(&self, p: (i32, i32)) -> f32 {
+        let x = (p.0 - self.origin.0).clamp(0, self.width as i32 - 1) as usize;
+        let z = (p.1 - self.origin.1).clamp(0, self.height as i32 - 1) as usize;
+        self.data[x + z * self.width]
+    }
+
+    /// whether any of a chunk is over the heightmap
+    pub fn covers(&self, seed: (f32, f32)) -> bool {
+        let n = (CHUNK_SIZE - 1) as i32;
+        let (x, z) = (seed.0 as i32 * n, seed.1 as i32 * n);
+        x + n >= self.origin.0 && z + n >= self.origin.1
+            && x < self.origin.0 + self.width as i32 && z < self.origin.1 + self.height as i32
+    }
+
+    /// the hightmap for a chunk, None if the chunk is not over the heightmap at all
+    pub fn chunk(&self, seed: (f32, f32)) -> Option<ChunkData<f32>> {
+        if !self.covers(seed) {
+            return None;
+        }
+        let n = (CHUNK_SIZE - 1) as i32;
+        let (x, z) = (seed.0 as i32 * n, seed.1 as i32 * n);
+        let mut out = Box::new([0.0; crate::map::CHUNK_SQSIZE]);
+        for (i, h) in out.iter_mut().enumerate() {
+            *h = self.get((x + (i % CHUNK_SIZE) as i32, z + (i / CHUNK_SIZE) as i32));
+        }
+        Some(out)
+    }
+
+    /// a mesh in world units with the first sample at the origin, triangulated like chunktomesh
+    /// the uvs stretch over the whole heightmap, so a stitched chunk texture lines up with it
+    pub fn mesh(&self) -> Mesh {
+        let (w, h) = (self.width, self.height);
+        let at = |x: usize, z: usize| self.data[x.min(w - 1) + z.min(h - 1) * w];
+        let mut position = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        for z in 0..h {
+            for x in 0..w {
+                position.push([x as f32 * VOXEL_SCALE, at(x, z) * VOXEL_SCALE, z as f32 * VOXEL_SCALE]);
+                // central differences, one sided on the edges
+                let dx = (at(x + 1, z) - at(x.saturating_sub(1), z)) / ((x + 1).min(w - 1) - x.saturating_sub(1)) as f32;
+                let dz = (at(x, z + 1) - at(x, z.saturating_sub(1))) / ((z + 1).min(h - 1) - z.saturating_sub(1)) as f32;
+                normals.push(Vec3::new(-dx, 1.0, -dz).normalize().to_array());
+                uvs.push([x as f32 / w as f32, z as f32 / h as f32]);
+            }
+        }
+        let mut indeces: Vec<u32> = Vec::new();
+        for z in 0..h - 1 {
+            for x in 0..w - 1 {
+                let i = (x + z * w) as u32;
+                let (nx, ny, nxy) = (i + 1, i + w as u32, i + w as u32 + 1);
+                indeces.extend([nx, i, ny, nx, ny, nxy]);
+            }
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indeces)));
+        mesh
+    }
+
+    /// bilinear resampling, factor is how many new samples there are for each old one along x and z
+    pub fn resample(&self, factor: (f32, f32)) -> Heightmap {
+        let width = (((self.width - 1) as f32 * factor.0).round() as usize + 1).max(2);
+        let height = (((self.height - 1) as f32 * factor.1).round() as usize + 1).max(2);
+        let at = |x: usize, z: usize| self.data[x.min(self.width - 1) + z.min(self.height - 1) * self.width];
+        let data = (0..width * height).map(|i| {
+            let fx = (i % width) as f32 / factor.0;
+            let fz = (i / width) as f32 / factor.1;
+            let (x, z) = (fx as usize, fz as usize);
+            let (tx, tz) = (fx - x as f32, fz - z as f32);
+            let a = at(x, z) + (at(x + 1, z) - at(x, z)) * tx;
+            let b = at(x, z + 1) + (at(x + 1, z + 1) - at(x, z + 1)) * tx;
+            a + (b - a) * tz
+        }).collect();
+        let origin = ((self.origin.0 as f32 * factor.0).round() as i32, (self.origin.1 as f32 * factor.1).round() as i32);
+        Heightmap {width, height, origin, data}
+    }
+
+    /// write the heightmap and its sidecar, u16 and png use the full range between the lowest and highest point
+    pub fn write(&self, path: &Path, format: Format) -> Result<(), String> {
+        let lo = self.data.iter().cloned().fold(f32::MAX, f32::min);
+        let hi = self.data.iter().cloned().fold(f32::MIN, f32::max);
+        let (scale, offset) = match format {
+            Format::F32 => (1.0, 0.0),
+            _ => ((hi - lo).max(f32::EPSILON) / u16::MAX as f32, lo),
+        };
+        let quantized = || -> Vec<u16> {
+            self.data.iter().map(|h| ((h - offset) / scale).round().clamp(0.0, u16::MAX as f32) as u16).collect()
+        };
+        let err = |e: String| format!("cant write {}: {}", path.display(), e);
+        match format {
+            Format::F32 => {
+                let bytes: Vec<u8> = self.data.iter().flat_map(|h| h.to_le_bytes()).collect();
+                std::fs::write(path, bytes).map_err(|e| err(e.to_string()))?
+            }
+            Format::U16 => {
+                let bytes: Vec<u8> = quantized().iter().flat_map(|h| h.to_le_bytes()).collect();
+                std::fs::write(path, bytes).map_err(|e| err(e.to_string()))?
+            }
+            Format::Png16 => {
+                let image = image::ImageBuffer::<image::Luma<u16>, _>::from_raw(self.width as u32, self.height as u32, quantized()).unwrap();
+                image.save(path).map_err(|e| err(e.to_string()))?
+            }
+        }
+        let sidecar = Sidecar {
+            width: self.width,
+            height: self.height,
+            format,
+            scale,
+            offset,
+            spacing: VOXEL_SCALE,
+            origin: self.origin,
+        };
+        let s = ron::ser::to_string_pretty(&sidecar, ron::ser::PrettyConfig::new()).unwrap();
+        std::fs::write(sidecar_path(path), s).map_err(|e| format!("cant write sidecar: {}", e))
+    }
+
+    /// read a heightmap, raw files need a sidecar, a png without one covers PNG_RANGE points from the world origin
+    /// hightmaps with a different spacing are resampled to VOXEL_SCALE
+    pub fn read(path: &Path) -> Result<Heightmap, String> {
+        let err = |e: String| format!("cant read {}: {}", path.display(), e);
+        let sidecar = match std::fs::read_to_string(sidecar_path(path)) {
+            Ok(s) => Some(ron::from_str::<Sidecar>(&s).map_err(|e| format!("cant parse sidecar: {}", e))?),
+            Err(_) => None,
+        };
+        let format = sidecar.as_ref().map_or_else(|| Format::from_path(path), |s| Ok(s.format))?;
+        let (width, height, samples): (usize, usize, Vec<f32>) = match format {
+            Format::Png16 => {
+                let image = image::open(path).map_err(|e| err(e.to_string()))?.into_luma16();
+                let (w, h) = image.dimensions();
+                (w as usize, h as usize, image.into_raw().into_iter().map(|v| v as f32).collect())
+            }
+            Format::F32 | Format::U16 => {
+                let s = sidecar.as_ref().ok_or_else(|| err("raw heightmaps need a sidecar".to_string()))?;
+                let bytes = std::fs::read(path).map_err(|e| err(e.to_string()))?;
+                let samples = if format == Format::F32 {
+                    bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+                } else {
+                    bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]) as f32).collect()
+                };
+                (s.width, s.height, samples)
+            }
+        };
+        if samples.len() != width * height || width < 2 || height < 2 {
+            return Err(err(format!("expected {}x{} samples but found {}", width, height, samples.len())));
+        }
+        let (scale, offset, spacing, origin) = match &sidecar {
+            Some(s) => (s.scale, s.offset, s.spacing, s.origin),
+            None => (PNG_RANGE / u16::MAX as f32, 0.0, VOXEL_SCALE, (0, 0)),
+        };
+        let hm = Heightmap {
+            width,
+            height,
+            origin,
+            data: samples.into_iter().map(|v| v * scale + offset).collect(),
+        };
+        Ok(if (spacing - VOXEL_SCALE).abs() > 1e-4 {hm.resample((spacing / VOXEL_SCALE, spacing / VOXEL_SCALE))} else {hm})
+    }
+}
+
+/// a heightmap chunks are generated from where it covers them, instead of the procedural hights
+#[derive(Resource, Default)]
+pub struct Imported(pub Option<Heightmap>);
+
+#[test]
+fn heightmaps_survive_a_round_trip() {
+    let hm = Heightmap::stitch((-1, 0), (0, 1), |s| crate::map::genhightmap((s.0 as f32, s.1 as f32), 0));
+    assert_eq!(hm.width, 2 * (CHUNK_SIZE - 1) + 1);
+    // cutting a chunk back out gives the chunk
+    assert_eq!(hm.chunk((0.0, 1.0)).unwrap(), crate::map::genhightmap((0.0, 1.0), 0));
+    assert!(hm.chunk((5.0, 5.0)).is_none());
+
+    let lo = hm.data.iter().cloned().fold(f32::MAX, f32::min);
+    let hi = hm.data.iter().cloned().fold(f32::MIN, f32::max);
+    let dir = std::env::temp_dir().join(format!("terrain-heightmap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for format in [Format::F32, Format::U16, Format::Png16] {
+        let path = dir.join(format!("h.{}", format.extension()));
+        hm.write(&path, format).unwrap();
+        let back = Heightmap::read(&path).unwrap();
+        assert_eq!((back.width, back.height, back.origin), (hm.width, hm.height, hm.origin));
+        // 16 bit formats are off by at most half a step
+        let tolerance = if format == Format::F32 {0.0} else {(hi - lo) / u16::MAX as f32 * 0.51};
+        let error = hm.data.iter().zip(back.data.iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(error <= tolerance, "{:?} is off by {}", format, error);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
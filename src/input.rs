@@ -1,114 +1,387 @@
-use bevy::input::keyboard::KeyboardInput;
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::prelude::*;
 use bevy::render::camera::Camera;
-use bevy::math::Vec3A;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-/// current state of keyboard, and a timer for moving the camera
+/// where the input map is loaded from, relative to the working directory
+pub const BINDINGS_FILE: &str = "bindings.ron";
+
+/// things the user can do, inputs are mapped to actions by `Bindings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    Ascend,
+    Descend,
+    YawLeft,
+    YawRight,
+    LookLeft,
+    LookRight,
+    LookUp,
+    LookDown,
+    DebugDump,
+    ReleaseCursor,
+    DumpBindings,
+    ReloadBindings,
+    SaveBookmark,
+    NextBookmark,
+    PreviousBookmark,
+    CycleView,
+    ToggleTriplanar,
+    PauseTime,
+    TimeForward,
+    TimeBack,
+}
+
+/// a modifier that must be held for a chord to trigger, either side of the keyboard counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+}
+
+impl Modifier {
+    fn held(&self, keys: &Input<KeyCode>) -> bool {
+        match self {
+            Modifier::Shift => keys.any_pressed([KeyCode::LShift, KeyCode::RShift]),
+            Modifier::Control => keys.any_pressed([KeyCode::LControl, KeyCode::RControl]),
+            Modifier::Alt => keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]),
+        }
+    }
+}
+
+/// which side of a gamepad axis triggers a binding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+/// a physical input that can trigger an action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    /// a key pressed while all the modifiers are held
+    Chord(Vec<Modifier>, KeyCode),
+    /// a button on any connected gamepad
+    GamepadButton(GamepadButtonType),
+    /// one side of an axis on any gamepad
+    GamepadAxis(GamepadAxisType, AxisDirection),
+}
+
+/// rescale an analog value so the dead zone reads as 0.0 and full deflection as 1.0
+fn apply_dead_zone(v: f32, dead_zone: f32) -> f32 {
+    ((v - dead_zone) / (1.0 - dead_zone)).clamp(0.0, 1.0)
+}
+
+/// everything bindings can read from
+#[derive(SystemParam)]
+pub struct RawInputs<'w> {
+    keys: Res<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    button_axes: Res<'w, Axis<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+}
+
+impl Binding {
+    /// how far the input is pushed, 0.0 to 1.0, keys are either 0.0 or 1.0
+    fn value(&self, dead_zone: f32, raw: &RawInputs) -> f32 {
+        match self {
+            Binding::Key(k) => raw.keys.pressed(*k) as i32 as f32,
+            Binding::Chord(m, k) => (raw.keys.pressed(*k) && m.iter().all(|m| m.held(&raw.keys))) as i32 as f32,
+            // triggers are buttons with an analog value
+            Binding::GamepadButton(b) => raw.gamepads.iter()
+                .map(|g| GamepadButton::new(g, *b))
+                .map(|b| match raw.button_axes.get(b) {
+                    Some(v) => apply_dead_zone(v, dead_zone),
+                    None => raw.buttons.pressed(b) as i32 as f32,
+                })
+                .fold(0.0, f32::max),
+            Binding::GamepadAxis(a, d) => raw.gamepads.iter()
+                .filter_map(|g| raw.axes.get(GamepadAxis::new(g, *a)))
+                .map(|v| match d {
+                    AxisDirection::Positive => v,
+                    AxisDirection::Negative => -v,
+                })
+                .map(|v| apply_dead_zone(v, dead_zone))
+                .fold(0.0, f32::max),
+        }
+    }
+}
+
+fn default_dead_zone() -> f32 {0.15}
+fn default_look_sensitivity() -> f32 {2.5}
+
+/// the input map, every action has a list of bindings, any of which will trigger it
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
+pub struct Bindings {
+    /// analog inputs below this are ignored
+    #[serde(default = "default_dead_zone")]
+    pub dead_zone: f32,
+    /// radians/s turned with a look action fully pushed
+    #[serde(default = "default_look_sensitivity")]
+    pub look_sensitivity: f32,
+    pub actions: Vec<(Action, Vec<Binding>)>
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        use Binding::*;
+        use AxisDirection::*;
+        Bindings {
+            dead_zone: default_dead_zone(),
+            look_sensitivity: default_look_sensitivity(),
+            actions: vec![
+                (Action::MoveForward, vec![Key(KeyCode::W), GamepadAxis(GamepadAxisType::LeftStickY, Positive)]),
+                (Action::MoveBack, vec![Key(KeyCode::S), GamepadAxis(GamepadAxisType::LeftStickY, Negative)]),
+                (Action::MoveLeft, vec![Key(KeyCode::A), GamepadAxis(GamepadAxisType::LeftStickX, Negative)]),
+                (Action::MoveRight, vec![Key(KeyCode::D), GamepadAxis(GamepadAxisType::LeftStickX, Positive)]),
+                (Action::Ascend, vec![Key(KeyCode::LShift), GamepadButton(GamepadButtonType::RightTrigger2)]),
+                (Action::Descend, vec![Key(KeyCode::LControl), GamepadButton(GamepadButtonType::LeftTrigger2)]),
+                (Action::YawLeft, vec![Key(KeyCode::Q)]),
+                (Action::YawRight, vec![Key(KeyCode::E)]),
+                (Action::LookLeft, vec![GamepadAxis(GamepadAxisType::RightStickX, Negative)]),
+                (Action::LookRight, vec![GamepadAxis(GamepadAxisType::RightStickX, Positive)]),
+                (Action::LookUp, vec![GamepadAxis(GamepadAxisType::RightStickY, Positive)]),
+                (Action::LookDown, vec![GamepadAxis(GamepadAxisType::RightStickY, Negative)]),
+                (Action::DebugDump, vec![Key(KeyCode::P), GamepadButton(GamepadButtonType::Select)]),
+                (Action::ReleaseCursor, vec![Key(KeyCode::Escape)]),
+                (Action::DumpBindings, vec![Key(KeyCode::F1)]),
+                (Action::ReloadBindings, vec![Key(KeyCode::F5)]),
+                (Action::SaveBookmark, vec![Key(KeyCode::B)]),
+                (Action::NextBookmark, vec![Key(KeyCode::RBracket)]),
+                (Action::PreviousBookmark, vec![Key(KeyCode::LBracket)]),
+                (Action::CycleView, vec![Key(KeyCode::F3)]),
+                (Action::ToggleTriplanar, vec![Key(KeyCode::F4)]),
+                (Action::PauseTime, vec![Key(KeyCode::T)]),
+                (Action::TimeForward, vec![Key(KeyCode::Period)]),
+                (Action::TimeBack, vec![Key(KeyCode::Comma)]),
+            ]
+        }
+    }
+}
+
+impl Bindings {
+    /// parse an input map from ron
+    pub fn from_ron(s: &str) -> Result<Bindings, String> {
+        ron::from_str(s).map_err(|e| format!("cant parse bindings: {}", e))
+    }
+
+    /// serialize the input map to ron, in the same format from_ron reads
+    pub fn to_ron(&self) -> String {
+        let config = ron::ser::PrettyConfig::new().depth_limit(3);
+        ron::ser::to_string_pretty(self, config).unwrap()
+    }
+
+    /// read an input map from a file
+    pub fn load(path: &str) -> Result<Bindings, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("cant read {}: {}", path, e))?;
+        Bindings::from_ron(&s)
+    }
+}
+
+/// the current state of all actions, filled in from `Bindings` every frame
+#[derive(Debug, Default, Resource)]
+pub struct ActionState {
+    values: HashMap<Action, f32>,
+    just_pressed: HashSet<Action>,
+}
+
+impl ActionState {
+    /// how far the action is pushed, 0.0 to 1.0
+    pub fn value(&self, a: Action) -> f32 {
+        self.values.get(&a).copied().unwrap_or(0.0)
+    }
+
+    /// was the action started this frame
+    pub fn just_pressed(&self, a: Action) -> bool {
+        self.just_pressed.contains(&a)
+    }
+
+    /// combine two opposing actions into -1.0 to 1.0
+    pub fn axis(&self, pos: Action, neg: Action) -> f32 {
+        self.value(pos) - self.value(neg)
+    }
+}
+
+/// tuning and state for the fly camera
 #[derive(Debug, Resource)]
-pub struct InputState{
-    w: bool,
-    a: bool,
-    s: bool,
-    d: bool,
-    q: bool,
-    e: bool,
-    shift: bool,
-    ctrl: bool,
-    movetimer: Timer
-}
-
-/// add the required resources
+pub struct FlyCamera {
+    /// horizontal speed (units/s)
+    pub speed: f32,
+    /// vertical speed as a fraction of speed
+    pub vertical: f32,
+    /// how fast velocity aproches the target velocity (1/s), higher is snappier
+    pub acceleration: f32,
+    /// radians per pixel of mouse movement
+    pub sensitivity: f32,
+    /// radians/s for keyboard yaw, look actions use Bindings::look_sensitivity
+    pub yaw_rate: f32,
+    /// limit on pitch, so the camera never flips over
+    pub max_pitch: f32,
+    /// speed multiplier per scroll line
+    pub scroll_factor: f32,
+    pub velocity: Vec3,
+}
+
+impl Default for FlyCamera {
+    fn default() -> FlyCamera {
+        FlyCamera {
+            speed: 18.0,
+            vertical: 1.0/3.0,
+            acceleration: 8.0,
+            sensitivity: 0.002,
+            yaw_rate: 1.5,
+            max_pitch: 1.54,
+            scroll_factor: 1.2,
+            velocity: Vec3::ZERO,
+        }
+    }
+}
+
+/// add the required resources, the input map is read from BINDINGS_FILE if it exists
 pub fn set_up(
     mut commands: Commands,
 ) {
-    commands.insert_resource(InputState { 
-        w: false,
-        a: false,
-        s: false,
-        d: false,
-        q: false,
-        e: false, 
-        shift: false, 
-        ctrl: false, 
-        movetimer: Timer::from_seconds(1.0/30.0, TimerMode::Repeating)
-    })
-}
-
-/// poll keyboadevents and timer
+    let bindings = Bindings::load(BINDINGS_FILE).unwrap_or_else(|e| {
+        println!("{}, using default bindings", e);
+        Bindings::default()
+    });
+    commands.insert_resource(bindings);
+    commands.insert_resource(ActionState::default());
+    commands.insert_resource(FlyCamera::default());
+}
+
+/// fill the action state from the keyboard and gamepads using the input map
 /// NOTE set_up must be run first with commands add_startup_system()
 pub fn keyboard_events(
+    raw: RawInputs,
+    bindings: Res<Bindings>,
+    mut state: ResMut<ActionState>
+) {
+    // the strongest binding wins
+    let values: HashMap<Action, f32> = bindings.actions.iter()
+        .map(|(a, b)| (*a, b.iter()
+            .map(|b| b.value(bindings.dead_zone, &raw))
+            .fold(0.0, f32::max)))
+        .filter(|(_, v)| *v > 0.0)
+        .collect();
+    state.just_pressed = values.keys().filter(|a| !state.values.contains_key(a)).copied().collect();
+    state.values = values;
+}
+
+/// dump or reload the input map
+pub fn rebind(
+    state: Res<ActionState>,
+    mut bindings: ResMut<Bindings>,
+) {
+    if state.just_pressed(Action::DumpBindings) {
+        println!("{}", bindings.to_ron());
+    }
+    if state.just_pressed(Action::ReloadBindings) {
+        match Bindings::load(BINDINGS_FILE) {
+            Ok(b) => {
+                *bindings = b;
+                println!("reloaded {}", BINDINGS_FILE);
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// grab the cursor on click, release it with ReleaseCursor
+pub fn grab_cursor(
+    buttons: Res<Input<MouseButton>>,
+    state: Res<ActionState>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let mut window = match windows.get_single_mut() {
+        Ok(w) => w,
+        Err(_) => return
+    };
+    if buttons.just_pressed(MouseButton::Left) {
+        window.cursor.grab_mode = CursorGrabMode::Locked;
+        window.cursor.visible = false;
+    }
+    if state.just_pressed(Action::ReleaseCursor) {
+        window.cursor.grab_mode = CursorGrabMode::None;
+        window.cursor.visible = true;
+    }
+}
+
+/// mouse movement and the window it is grabbed by
+#[derive(SystemParam)]
+pub struct Mouse<'w, 's> {
+    motion_evr: EventReader<'w, 's, MouseMotion>,
+    scroll_evr: EventReader<'w, 's, MouseWheel>,
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+/// move and rotate the camera, scaled by frame time
+pub fn move_camera(
     time: Res<Time>,
-    mut key_evr: EventReader<KeyboardInput>,
+    state: Res<ActionState>,
+    bindings: Res<Bindings>,
+    mut fly: ResMut<FlyCamera>,
+    mut mouse: Mouse,
     mut cameras: Query<&mut Transform, With<Camera>>,
-    mut state: ResMut<InputState>
 ) {
-    use bevy::input::ButtonState;
-    let mut dbg = false;
-
-    for ev in key_evr.iter() {
-        match ev.state {
-            ButtonState::Pressed => {
-                match ev.key_code {
-                    Some(KeyCode::W) => state.w = true,
-                    Some(KeyCode::A) => state.a = true,
-                    Some(KeyCode::S) => state.s = true,
-                    Some(KeyCode::D) => state.d = true,
-                    Some(KeyCode::E) => state.e = true,
-                    Some(KeyCode::Q) => state.q = true,
-                    Some(KeyCode::P) => dbg = true,
-                    Some(KeyCode::LShift) => state.shift = true,
-                    Some(KeyCode::LControl) => state.ctrl = true,
-                    _ => ()
-                }
-            }
-            ButtonState::Released => {
-                match ev.key_code {
-                    Some(KeyCode::W) => state.w = false,
-                    Some(KeyCode::A) => state.a = false,
-                    Some(KeyCode::S) => state.s = false,
-                    Some(KeyCode::D) => state.d = false,
-                    Some(KeyCode::E) => state.e = false,
-                    Some(KeyCode::Q) => state.q = false,
-                    Some(KeyCode::LShift) => state.shift = false,
-                    Some(KeyCode::LControl) => state.ctrl = false,
-                    _ => ()
-                }
-            }
+    let dt = time.delta_seconds();
+
+    // only look around when the cursor is grabbed, otherwise the mouse is for the os
+    let grabbed = mouse.windows.get_single().is_ok_and(|w| w.cursor.grab_mode != CursorGrabMode::None);
+    let mut look = Vec2::ZERO;
+    for ev in mouse.motion_evr.iter() {
+        if grabbed {
+            look += ev.delta;
         }
     }
-    
-    if !state.movetimer.tick(time.delta()).just_finished() {
-        return;
-    }
-    
-    let mut x = 0.0;
-    let mut z = 0.0;
-    let mut r = 0.0;
-    let mut y = 0.0;
-    
-    if state.w {z -= 0.6;}
-    if state.s {z += 0.6;}
-    
-    if state.shift {y += 0.2;}
-    if state.ctrl {y -= 0.2;}
-    
-    if state.a {x -= 0.6;}
-    if state.d {x += 0.6;}
-    
-    if state.q {r += 0.05;}
-    if state.e {r -= 0.05;}
-    
-    let q = Quat::from_rotation_y(r);
-    
+
+    for ev in mouse.scroll_evr.iter() {
+        fly.speed *= fly.scroll_factor.powf(ev.y);
+    }
+    fly.speed = fly.speed.clamp(0.5, 500.0);
+
+    // movement in camera space, y is world up
+    let x = state.axis(Action::MoveRight, Action::MoveLeft);
+    let z = state.axis(Action::MoveBack, Action::MoveForward);
+    let y = state.axis(Action::Ascend, Action::Descend);
+    let r = state.axis(Action::YawLeft, Action::YawRight);
+    let stick = Vec2::new(
+        state.axis(Action::LookRight, Action::LookLeft),
+        state.axis(Action::LookUp, Action::LookDown),
+    ) * bindings.look_sensitivity * dt;
+
+    // framerate independent exponential aproach to the target velocity
+    let damping = 1.0 - (-fly.acceleration * dt).exp();
+
     for mut c in cameras.iter_mut() {
-        if dbg {println!("{:?}", c);}
-        let r = c.rotation.mul_vec3a(Vec3A::new(x,0.0,z));
-        c.translation.x += r.x;
-        c.translation.z += r.z;
-        c.translation.y += y;
-        //c.rotation = c.rotation.mul_quat(q);
-        c.rotation = q.mul_quat(c.rotation)
-    }
-    
-    
+        if state.just_pressed(Action::DebugDump) {println!("{:?}", c);}
+
+        let (mut yaw, mut pitch, _) = c.rotation.to_euler(EulerRot::YXZ);
+        yaw += r * fly.yaw_rate * dt - stick.x - look.x * fly.sensitivity;
+        pitch += stick.y - look.y * fly.sensitivity;
+        pitch = pitch.clamp(-fly.max_pitch, fly.max_pitch);
+        c.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0);
+
+        let flat = Quat::from_rotation_y(yaw) * Vec3::new(x, 0.0, z);
+        let target = flat.clamp_length_max(1.0) * fly.speed + Vec3::Y * y * fly.speed * fly.vertical;
+        let v = fly.velocity;
+        fly.velocity = v + (target - v) * damping;
+        c.translation += fly.velocity * dt;
+    }
+}
+
+#[test]
+fn bindings_file_matches_default() {
+    let file = Bindings::load(BINDINGS_FILE).unwrap();
+    assert_eq!(file, Bindings::default());
+    assert_eq!(Bindings::from_ron(&file.to_ron()).unwrap(), file);
 }
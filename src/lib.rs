@@ -0,0 +1,135 @@
+//! procedural terrain, everything the game is made of so tools and examples can use it too
+
+use bevy::prelude::*;
+extern crate nalgebra as na;
+
+/// liner interpolation ac is the coificent for a
+pub fn lerp(a: f32,b: f32, ac: f32) -> f32 {
+    a*ac + b*(1.0-ac)
+}
+
+/// blend colours channel by channel, t from 0.0 (a) to 1.0 (b)
+pub fn mix<const N: usize>(a: [f32; N], b: [f32; N], t: f32) -> [f32; N] {
+    let t = t.clamp(0.0, 1.0);
+    let mut out = a;
+    for (o, b) in out.iter_mut().zip(b.iter()) {
+        *o += (b - *o) * t;
+    }
+    out
+}
+
+pub mod map;
+pub mod input;
+pub mod chunk;
+pub mod loader;
+pub mod reg;
+pub mod console;
+pub mod bookmark;
+pub mod debug;
+pub mod material;
+pub mod water;
+pub mod sky;
+pub mod sun;
+pub mod scatter;
+pub mod road;
+pub mod poi;
+pub mod cave;
+pub mod export;
+pub mod heightmap;
+pub mod dem;
+pub mod pyramid;
+pub mod serve;
+pub mod net;
+pub mod packed;
+pub mod lru;
+#[cfg(feature = "physics")]
+pub mod physics;
+
+
+/// the terrain, its loading, rendering and controls, the app still needs DefaultPlugins
+/// insert WorldSeed and ViewDistance before adding it to start somewhere other than the defaults
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<chunk::WorldSeed>()
+            .init_resource::<loader::ViewDistance>()
+            .init_resource::<heightmap::Imported>()
+            .init_resource::<packed::Cache>()
+            .insert_resource(road::Roads::default())
+            .init_resource::<road::Planning>()
+            .insert_resource(poi::Pois::default())
+            .add_event::<console::Command>()
+            .add_event::<poi::PoiFound>()
+            .add_event::<bookmark::Teleport>()
+            .add_event::<road::RoadChanged>()
+            .add_startup_system(console::init)
+            .add_system(console::poll)
+            .add_startup_system(bookmark::init)
+            .add_system(bookmark::hotkeys.after(input::keyboard_events))
+            .add_system(bookmark::commands.after(console::poll))
+            .add_system(bookmark::teleport.after(bookmark::hotkeys).after(bookmark::commands).before(loader::load))
+            .add_startup_system(input::set_up)
+            .add_system(input::keyboard_events)
+            .add_system(input::grab_cursor.after(input::keyboard_events))
+            .add_system(input::move_camera.after(input::keyboard_events))
+            .add_system(input::rebind.after(input::keyboard_events))
+            .add_startup_system(loader::init)
+            .add_system(loader::commands.after(console::poll))
+            .add_system(loader::load.after(loader::commands))
+            .add_system(loader::unload)
+            .add_startup_system(setup)
+            .add_plugin(MaterialPlugin::<material::TerrainMaterial>::default())
+            .add_startup_system(material::init)
+            .add_system(material::build)
+            .add_system(material::toggle_triplanar.after(input::keyboard_events))
+            .add_plugin(MaterialPlugin::<water::WaterMaterial>::default())
+            .add_startup_system(water::init)
+            .add_system(chunk::generate_maps.after(material::build))
+            .add_startup_system(debug::init)
+            .add_system(debug::cycle.after(input::keyboard_events))
+            .add_system(debug::apply.after(debug::cycle).after(chunk::generate_maps))
+            .add_system(debug::place_labels.after(debug::apply))
+            .add_startup_system(sky::init)
+            .add_system(sky::commands.after(console::poll))
+            .add_startup_system(sun::init)
+            .add_system(sun::tick.after(input::keyboard_events))
+            .add_system(sun::commands.after(console::poll).after(sun::tick))
+            .add_system(sun::update.after(sun::commands).before(sky::apply))
+            .add_system(sun::shadows)
+            .add_system(sky::apply.after(sky::commands))
+            .add_system(sky::follow.after(input::move_camera).after(bookmark::teleport))
+            .add_startup_system(scatter::init)
+            .add_system(scatter::spawn.after(chunk::generate_maps))
+            .add_system(road::clear_on_world_change.after(bookmark::teleport))
+            .add_system(road::commands.after(console::poll).after(road::clear_on_world_change).before(loader::load))
+            .add_system(road::finish.after(road::commands).before(loader::load))
+            .add_system(poi::prepare.before(chunk::generate_maps))
+            .add_system(poi::announce.after(chunk::generate_maps))
+            .add_system(poi::log_found.after(poi::announce))
+            .add_system(poi::commands.after(console::poll))
+            .add_system(net::receive.before(chunk::generate_maps))
+            .add_system(net::commands.after(console::poll));
+        #[cfg(feature = "physics")]
+        app
+            .add_plugin(bevy_rapier3d::prelude::RapierPhysicsPlugin::<bevy_rapier3d::prelude::NoUserData>::default())
+            .add_system(physics::spawn.after(chunk::generate_maps));
+    }
+}
+
+/// add boilerplate entitys
+fn setup(
+    mut commands: Commands,
+) {
+    // set up the camera
+    let camera = Camera3dBundle {
+        transform: Transform::from_xyz(-2.0, 5.0, -2.0).looking_at(Vec3::new(3.0,0.0,3.0), Vec3::Y),
+        ..default()
+    };
+     
+    // camera, sky::apply sets up the fog
+    commands.spawn((camera, FogSettings::default()));
+    
+    // the lights are spawned by sun::init
+}
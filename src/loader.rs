@@ -2,10 +2,26 @@ use bevy::prelude::*;
 use std::collections::HashMap;
 use bevy::render::camera::Camera;
 use crate::map;
-use crate::chunk::Map;
+use crate::chunk::{Map, WorldSeed};
+use crate::console::Command;
+use crate::road::RoadChanged;
 
-/// Manhatan distance for loaded chunks.
-const loaded_distance:i32 = 1;
+/// how many chunks are loaded in each direction arround the camera's chunk
+#[derive(Resource, Clone, Copy, PartialEq, Eq)]
+pub struct ViewDistance(pub i32);
+
+impl Default for ViewDistance {
+    fn default() -> ViewDistance {
+        ViewDistance(2)
+    }
+}
+
+impl ViewDistance {
+    /// the distance from the camera that is always loaded, in world units
+    pub fn radius(&self) -> f32 {
+        self.0 as f32 * map::getchunksize()
+    }
+}
 
 #[derive(Component)]
 pub struct UnloadMarker;
@@ -26,18 +42,41 @@ pub fn init(mut commands: Commands) {
 pub fn load(
     mut commands: Commands,
     cameras: Query<&Transform, With<Camera>>,
-    mut data: ResMut<Data>
-    
+    mut data: ResMut<Data>,
+    world: Res<WorldSeed>,
+    view: Res<ViewDistance>,
+    mut roads: EventReader<RoadChanged>,
 ) {
+    let loaded_distance = view.0;
     let c = cameras.iter().next().unwrap();
     
+    // a new world, nothing loaded is valid
+    if world.is_changed() && !world.is_added() {
+        for (k, id) in data.loader.drain() {
+            println!("{:?} marked for unload",k);
+            commands.entity(id).insert(UnloadMarker {});
+        }
+    }
+    
+    // new or removed roads, only the chunks under them are reloaded
+    for r in roads.iter() {
+        let under: Vec<(i32,i32)> = data.loader.keys()
+            .filter(|k| k.0 >= r.from.0 && k.0 <= r.to.0 && k.1 >= r.from.1 && k.1 <= r.to.1)
+            .cloned()
+            .collect();
+        for k in under {
+            println!("{:?} marked for unload",k);
+            commands.entity(data.loader.remove(&k).unwrap()).insert(UnloadMarker {});
+        }
+    }
+    
     let s = map::getchunksize();
     
-    let x = (c.translation.x / s) as i32;
-    let y = (c.translation.z / s) as i32;
+    let x = (c.translation.x / s).floor() as i32;
+    let y = (c.translation.z / s).floor() as i32;
     
-    for cx in (x-loaded_distance)..(x+loaded_distance) {
-        for cy in (y-loaded_distance)..(y+loaded_distance) {
+    for cx in (x-loaded_distance)..=(x+loaded_distance) {
+        for cy in (y-loaded_distance)..=(y+loaded_distance) {
             match data.loader.get(&(cx,cy)) {
                 Some(_) => continue,
                 None => ()
@@ -66,6 +105,19 @@ pub fn load(
     
 }
 
+/// handle the view console command
+pub fn commands(
+    mut commands: EventReader<Command>,
+    mut view: ResMut<ViewDistance>,
+) {
+    for c in commands.iter() {
+        if let Command::ViewDistance(d) = c {
+            println!("view distance {} chunks", d);
+            *view = ViewDistance(*d);
+        }
+    }
+}
+
 pub fn unload(
     mut commands: Commands,
     maps: Query<&Map, With<UnloadMarker>>,
@@ -73,7 +125,7 @@ pub fn unload(
     for i in maps.iter() {
         match i.render {
             None => (),
-            Some(i) => commands.entity(i).despawn()
+            Some(i) => commands.entity(i).despawn_recursive()
         }
         match i.this {
             None => (),
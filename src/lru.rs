@@ -0,0 +1,59 @@
+//! a small least recently used cache, for the http server's responses and unloaded chunks
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// keeps the most recently used values, up to capacity
+pub struct Lru<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (u64, V)>,
+    /// the keys by when they were last used, so the oldest is found without looking at every entry
+    used: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Lru<K, V> {
+        Lru {capacity: capacity.max(1), tick: 0, entries: HashMap::new(), used: BTreeMap::new()}
+    }
+
+    pub fn get<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V> where K: std::borrow::Borrow<Q> {
+        let e = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let k = self.used.remove(&e.0).unwrap();
+        self.used.insert(self.tick, k);
+        e.0 = self.tick;
+        Some(e.1.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        match self.entries.get(&key) {
+            Some(e) => {
+                self.used.remove(&e.0);
+            }
+            None => if self.entries.len() >= self.capacity {
+                if let Some((_, oldest)) = self.used.pop_first() {
+                    self.entries.remove(&oldest);
+                }
+            },
+        }
+        self.tick += 1;
+        self.used.insert(self.tick, key.clone());
+        self.entries.insert(key, (self.tick, value));
+    }
+}
+
+#[test]
+fn lru_drops_the_least_recently_used() {
+    let mut lru = Lru::new(2);
+    lru.insert("a".to_string(), 1);
+    lru.insert("b".to_string(), 2);
+    lru.get("a");
+    lru.insert("c".to_string(), 3);
+    assert_eq!((lru.get("a"), lru.get("b"), lru.get("c")), (Some(1), None, Some(3)));
+    // inserting a key again uses it, so the other one goes
+    lru.insert("a".to_string(), 4);
+    lru.insert("d".to_string(), 5);
+    assert_eq!((lru.get("a"), lru.get("c"), lru.get("d")), (Some(4), None, Some(5)));
+    assert_eq!(lru.entries.len(), lru.used.len());
+}
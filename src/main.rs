@@ -2,88 +2,67 @@ use bevy::prelude::*;
 
 use bevy::{
     pbr::wireframe::WireframePlugin,
-//    render::options::{WgpuFeatures, WgpuOptions},
+    render::{RenderPlugin, settings::{WgpuFeatures, WgpuSettings}},
 };
-extern crate nalgebra as na;
+use std::path::Path;
+use terrain::{chunk, dem, heightmap, loader, net, TerrainPlugin};
 
-/// liner interpolation ac is the coificent for a
-pub fn lerp(a: f32,b: f32, ac: f32) -> f32 {
-    a*ac + b*(1.0-ac)
+const USAGE: &str = "usage: terrain [--seed n] [--view-distance n] [--connect host:port] [--import heightmap|dem.asc|dem.hgt] [--dem-scale metres] [--dem-exaggeration n] [--dem-detail n] [--dem-units metres|degrees]";
+
+/// stop before the window opens, with a message rather than a panic
+fn fail(e: String) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1);
 }
 
-mod map;
-mod input;
-mod chunk;
-mod loader;
-mod reg;
+/// read an option like `--seed <n>` from the command line
+fn arg<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|a| a == name)?;
+    match args.get(i + 1) {
+        Some(v) => Some(v.parse().unwrap_or_else(|_| fail(format!("{} cant be {}\n{}", name, v, USAGE)))),
+        None => fail(format!("{} needs a value\n{}", name, USAGE)),
+    }
+}
 
 fn main() {
-    App::new()
+    // every option is read before the window opens, so a bad one stops with a message straight away
+    // the default world is 0
+    let seed = chunk::WorldSeed(arg("--seed").unwrap_or(0));
+    let view = arg("--view-distance").map_or(loader::ViewDistance::default(), loader::ViewDistance);
+    // chunks under an imported heightmap or dem are generated from it
+    let imported = heightmap::Imported(arg::<String>("--import").map(|p| {
+        let options = dem::DemOptions {
+            metres_per_point: arg("--dem-scale"),
+            exaggeration: arg("--dem-exaggeration").unwrap_or(1.0),
+            detail: arg("--dem-detail").unwrap_or(0.0),
+            units: arg("--dem-units"),
+        };
+        dem::import(Path::new(&p), &options).unwrap_or_else(|e| fail(e))
+    }));
+    let mut app = App::new();
+    // chunks come from a server started with `terrain-gen host`
+    if let Some(addr) = arg::<String>("--connect") {
+        app.insert_resource(net::Remote::connect(&addr).unwrap_or_else(|e| fail(e)));
+    }
+    app
 //        .insert_resource(Window {
 //            title: "Terrain".to_string(),
 //            vsync: false,
 //            ..Default::default()
 //        })
-//         .insert_resource(WgpuOptions {
-//             features: WgpuFeatures {
-//                 // The Wireframe requires NonFillPolygonMode feature
-//                 features: vec![WgpuFeature::NonFillPolygonMode],
-//             },
-//             ..Default::default()
-//         })
 //        .insert_resource(Msaa { samples: 1 })
-        .add_plugins(DefaultPlugins)
+        .add_plugins(DefaultPlugins.set(RenderPlugin {
+            wgpu_settings: WgpuSettings {
+                // The Wireframe requires the line polygon mode
+                features: WgpuFeatures::POLYGON_MODE_LINE,
+                ..Default::default()
+            }
+        }))
         .add_plugin(WireframePlugin)
-        .add_startup_system(input::set_up)
-        .add_system(input::keyboard_events)
-        .add_startup_system(loader::init)
-        .add_system(loader::load)
-        .add_system(loader::unload)
-        .add_startup_system(setup)
-        .add_system(chunk::generate_maps)
+        .insert_resource(seed)
+        .insert_resource(view)
+        .insert_resource(imported)
+        .add_plugin(TerrainPlugin)
         .run();
 }
-
-/// start loading assets and add boilerplate entitys
-/// FIXME this leeks memory
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-) {
-    // load assets
-    let a: Handle<Image> = asset_server.load(map::ASSETS_GRASS);
-    let b: Handle<Image> = asset_server.load(map::ASSETS_WATER);
-    let c: Handle<Image> = asset_server.load(map::ASSETS_SAND);
-    let d: Handle<Image> = asset_server.load(map::ASSETS_SNOW);
-    let e: Handle<Image> = asset_server.load(map::ASSETS_STONE);
-    
-    
-    // set up the camera
-    let mut camera = Camera3dBundle {
-        transform: Transform::from_xyz(-2.0, 5.0, -2.0).looking_at(Vec3::new(3.0,0.0,3.0), Vec3::Y),
-        ..default()
-    };
-     
-    // camera
-    commands.spawn(camera);
-    
-    // Oh, no! memory leek.
-    use std::mem;
-    mem::forget(a);
-    mem::forget(b);
-    mem::forget(c);
-    mem::forget(d);   
-    mem::forget(e);
-    
-      // light
-      commands.spawn(DirectionalLightBundle {
-         directional_light: DirectionalLight {
-             illuminance: 30000.0,
-             shadows_enabled: true,
-             ..Default::default()
-         },
-         transform: Transform::from_xyz(-5.0, 5.0, -5.0).looking_at(Vec3::ZERO, Vec3::Y),
-          ..Default::default()
-      });
-    
-}
@@ -33,10 +33,45 @@ pub const CHUNK_SQSIZE: usize = CHUNK_SIZE*CHUNK_SIZE;
 /// size of sample on map`
 pub const VOXEL_SCALE: f32 = 0.4;
 
+/// hightmap thresholds used to pick textures
+pub const SNOW_HEIGHT: f32 = 22.0;
+pub const GRASS_HEIGHT: f32 = -0.3;
+pub const SAND_HEIGHT: f32 = -0.7;
+/// slope (from genslope) above which grass becomes stone
+pub const STONE_SLOPE: f32 = 1.5;
+/// hight of the water surface, the terrain is clipped to it so the sea is flat, see genseabed for the ground under it
+pub const SEA_LEVEL: f32 = -0.71;
+
 /// a row major array for hightmap data
 // x + z*CHUNK_SIZE
 pub type ChunkData<N> = Box<[N; CHUNK_SQSIZE]>;
 
+/// splitmix32 style hash, for picking things deterministicly from seeds
+pub fn hash(x: u32) -> u32 {
+    let mut h = x.wrapping_mul(0x9e3779b9);
+    h = (h ^ (h >> 16)).wrapping_mul(0x85ebca6b);
+    h = (h ^ (h >> 13)).wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+/// pick where in the endless world a world seed starts, seed 0 is at the origin
+/// returns an offset in chunks, small enough to keep f32 precision.
+pub fn world_origin(world: u32) -> (f32,f32) {
+    if world == 0 {
+        return (0.0, 0.0);
+    }
+    let h = hash(world);
+    let x = (h & 0xfff) as f32 - 2048.0;
+    let y = ((h >> 12) & 0xfff) as f32 - 2048.0;
+    (x, y)
+}
+
+/// the chunk seed actually generated for a chunk seed in a world
+pub fn world_chunk(seed: (f32,f32), world: u32) -> (f32,f32) {
+    let origin = world_origin(world);
+    (seed.0 + origin.0, seed.1 + origin.1)
+}
+
 /// calculate the size of the chunk mesh
 pub fn getchunksize() -> f32 {
     VOXEL_SCALE * (CHUNK_SIZE-1) as f32
@@ -48,6 +83,16 @@ pub fn genchunkreg(seed: (f32,f32)) -> reg::Regdata {
     reg::newreg(regseed)
 }
 
+/// create the 2x2 grid of regdata genchunk blends between.
+// this could be optimized
+pub fn genchunkregs(seed: (f32,f32)) -> [reg::Regdata;4] {
+    let reg   = genchunkreg(seed);
+    let regx  = genchunkreg((seed.0 + 1.0, seed.1 + 0.0));
+    let regy  = genchunkreg((seed.0 + 0.0, seed.1 + 1.0));
+    let regxy = genchunkreg((seed.0 + 1.0, seed.1 + 1.0));
+    [reg,regx,regy,regxy]
+}
+
 fn ravien(h: f32) -> f32 {
     if h > 5.0 && h < 7.0 {
             h - 6.7 
@@ -108,20 +153,52 @@ fn get_rotated_map(x: f32, y: f32, a: f32) -> f32 {
 
 /// generates a hightmap without any modifyers applyed
 fn get_base_hightmap(wx: f32, wy: f32) -> f32 {
-    get_rotated_map(wx / 256.0, wy / 256.0, 1.0) * 32.0 + 
-    get_rotated_map(wx / 128.0, wy / 128.0, 2.0) * 16.0 + 
-    get_rotated_map(wx / 64.0, wy / 64.0, 3.0) * 8.0 + 
-    get_rotated_map(wx / 32.0, wy / 32.0, 4.0) * 4.0 + 
-    get_rotated_map(wx / 16.0, wy / 16.0, 5.0) * 2.0 + 
-    get_rotated_map(wx / 8.0, wy / 8.0, 6.0) * 1.0 + 
+    let coarse =
+        get_rotated_map(wx / 256.0, wy / 256.0, 1.0) * 32.0 + 
+        get_rotated_map(wx / 128.0, wy / 128.0, 2.0) * 16.0 + 
+        get_rotated_map(wx / 64.0, wy / 64.0, 3.0) * 8.0 + 
+        get_rotated_map(wx / 32.0, wy / 32.0, 4.0) * 4.0 + 
+        get_rotated_map(wx / 16.0, wy / 16.0, 5.0) * 2.0;
+    add_detail(coarse, wx, wy)
+}
+
+/// only the finest octaves of get_base_hightmap, for adding detail to hights that come from elsewhere
+pub fn get_detail(wx: f32, wy: f32) -> f32 {
+    add_detail(0.0, wx, wy)
+}
+
+/// the finest octaves added to h one at a time, in the order get_base_hightmap always has so its hights stay the same
+fn add_detail(h: f32, wx: f32, wy: f32) -> f32 {
+    h +
+    get_rotated_map(wx / 8.0, wy / 8.0, 6.0) * 1.0 +
     get_rotated_map(wx / 4.0, wy / 4.0, 7.0) * 0.5 +
     get_rotated_map(wx / 2.0, wy / 2.0, 8.0) * 0.25
 }
 
+/// blend a region factor across a chunk, nx and ny are on a scale from 0.0 to 1.0
+/// regs is a row major array containg a 2x2 grid regions, with 0,0 being the chunk.
+pub fn blend_reg(regs: &[reg::Regdata;4], nx: f32, ny: f32, f: impl Fn(&reg::Regdata) -> f32) -> f32 {
+    lerp(
+        lerp(f(&regs[3]),f(&regs[2]),nx),
+        lerp(f(&regs[1]),f(&regs[0]),nx),
+        ny
+    )
+}
+
 /// create a hightmap
 /// regs is a row major array containg a 2x2 grid regions, with 0,0 being the chunk.
 /// the additional regons are used to blend generation
 pub fn genchunk(seed: (f32,f32),regs: &[reg::Regdata;4]) -> ChunkData<f32> {
+    let mut cdata = genseabed(seed, regs);
+    // clip to sea level
+    for h in cdata.iter_mut() {
+        *h = h.max(SEA_LEVEL);
+    }
+    cdata
+}
+
+/// genchunk without the clip to sea level, the ground under the water for how deep it is
+pub fn genseabed(seed: (f32,f32),regs: &[reg::Regdata;4]) -> ChunkData<f32> {
     // todo, use mabey uninit
     let mut cdata = [0.0_f32; CHUNK_SQSIZE];
     let perlin = Perlin::new();
@@ -141,42 +218,116 @@ pub fn genchunk(seed: (f32,f32),regs: &[reg::Regdata;4]) -> ChunkData<f32> {
         let wx = ox + x;
         let wy = oy + y;
         
-        *ptr = get_base_hightmap(wx, wy);
-        
-        let local_rev = lerp(
-            lerp(regs[3].raviens,regs[2].raviens,nx),
-            lerp(regs[1].raviens,regs[0].raviens,nx),
-            ny
-        );
-        
-        *ptr = lerp(ravien(*ptr),*ptr,local_rev);
-       
-        let local_clifs = lerp(
-            lerp(regs[3].clifs,regs[2].clifs,nx),
-            lerp(regs[1].clifs,regs[0].clifs,nx),
-            ny
-        );
-       
-        *ptr = lerp(clifs(*ptr),*ptr,local_clifs);
-        
-        let local_fiords = lerp(
-            lerp(regs[3].fiords,regs[2].fiords,nx),
-            lerp(regs[1].fiords,regs[0].fiords,nx),
-            ny
-        );
-        
-        *ptr = lerp(fiords(*ptr),*ptr,local_fiords);
-       
-        // clip to sea level
-        if *ptr < -0.71 {
-            *ptr = -0.71
-        }
+        *ptr = apply_regs(get_base_hightmap(wx, wy), regs, nx, ny);
         
         //*ptr = 0.0;
     }
     return Box::new(cdata)
 }
 
+/// the raviens, clifs and fiords of the regions arround a point
+/// nx and ny are on a scale from 0.0 to 1.0 across the chunk
+fn apply_regs(h: f32, regs: &[reg::Regdata;4], nx: f32, ny: f32) -> f32 {
+    let local_rev = blend_reg(regs, nx, ny, |r| r.raviens);
+    
+    let h = lerp(ravien(h),h,local_rev);
+   
+    let local_clifs = blend_reg(regs, nx, ny, |r| r.clifs);
+   
+    let h = lerp(clifs(h),h,local_clifs);
+    
+    let local_fiords = blend_reg(regs, nx, ny, |r| r.fiords);
+    
+    lerp(fiords(h),h,local_fiords)
+}
+
+/// the octaves of get_base_hightmap, as (size, rotation, weight)
+const OCTAVES: [(f32, f32, f32); 8] = [
+    (256.0, 1.0, 32.0),
+    (128.0, 2.0, 16.0),
+    (64.0, 3.0, 8.0),
+    (32.0, 4.0, 4.0),
+    (16.0, 5.0, 2.0),
+    (8.0, 6.0, 1.0),
+    (4.0, 7.0, 0.5),
+    (2.0, 8.0, 0.25),
+];
+
+/// x rounded to a whole number, by adding and taking away 1.5 * 2^23 so it does not become a libm call
+/// only for |x| < 2^22, plenty for world coordinates
+fn round_fast(x: f32) -> f32 {
+    const M: f32 = 12_582_912.0;
+    (x + M) - M
+}
+
+fn floor_fast(x: f32) -> f32 {
+    let r = round_fast(x);
+    r - (r > x) as i32 as f32
+}
+
+/// 1 for even n and -1 for odd n
+fn parity_sign(n: i32) -> f32 {
+    (1 - 2 * (n & 1)) as f32
+}
+
+/// sin(pi x) without calling sin, so loops over it can be vectorized
+/// x is reduced to [-0.5, 0.5] first, which is exact, so this is closer than (x*PI).sin() for large x
+fn sin_pi(x: f32) -> f32 {
+    let n = round_fast(x);
+    let r = x - n;
+    let r2 = r * r;
+    // taylor series of sin(pi r), off by less than 1e-7 on [-0.5, 0.5]
+    let s = r * (std::f32::consts::PI + r2 * (-5.167_712_8 + r2 * (2.550_164 + r2 * (-0.599_264_5 + r2 * (0.082_145_89 + r2 * -0.007_370_431)))));
+    s * parity_sign(n as i32)
+}
+
+/// get_2d_noise for whole numbers, sin(k) is sin_pi(k / pi) with the reduction in f64 so large k stay exact
+fn noise_at(fx: f32, fy: f32) -> f32 {
+    const M: f64 = 6_755_399_441_055_744.0;
+    let k = (fx * 11.0 + fy * 29.0) as f64 * std::f64::consts::FRAC_1_PI;
+    let n = (k + M) - M;
+    sin_pi((k - n) as f32) * parity_sign(n as i32)
+}
+
+/// get_base_hightmap for a whole chunk at once, a row at a time with the rotations worked out once
+/// matches get_base_hightmap to within BATCH_TOLERANCE, both round differently
+fn base_hightmap_batch(ox: f32, oy: f32) -> ChunkData<f32> {
+    let octaves = OCTAVES.map(|(size, a, weight)| (1.0 / size, a.cos(), a.sin(), weight));
+    let mut cdata: ChunkData<f32> = Box::new([0.0; CHUNK_SQSIZE]);
+    for (row, out) in cdata.chunks_exact_mut(CHUNK_SIZE).enumerate() {
+        let wy = oy + row as f32;
+        for (inv, c, s, weight) in octaves.iter() {
+            // the sizes are powers of two, so multiplying by inv is the same as dividing
+            let y = wy * inv;
+            for (x, h) in out.iter_mut().enumerate() {
+                let x = (ox + x as f32) * inv;
+                let (rx, ry) = (c * x - s * y, s * x + c * y);
+                *h += sin_pi(rx) * sin_pi(ry) * noise_at(floor_fast(rx), floor_fast(ry)) * weight;
+            }
+        }
+    }
+    cdata
+}
+
+/// how far base hights from the batch path can be from get_base_hightmap, in points
+/// get_base_fn loses precision as (x*PI) grows, so this is mostly the scalar path being off far from the origin
+/// the raviens, clifs and fiords are steps, so a hight within this of a step can end up on either side of it
+pub const BATCH_TOLERANCE: f32 = 0.05;
+
+/// genchunk with the batch path, within BATCH_TOLERANCE of it except next to steps
+pub fn genchunk_batch(seed: (f32,f32), regs: &[reg::Regdata;4]) -> ChunkData<f32> {
+    let ox = seed.0*(CHUNK_SIZE-1) as f32;
+    let oy = seed.1*(CHUNK_SIZE-1) as f32;
+    let mut cdata = base_hightmap_batch(ox, oy);
+    for (idx, h) in cdata.iter_mut().enumerate() {
+        let nx = (idx % CHUNK_SIZE) as f32 / CHUNK_SIZE as f32;
+        let ny = (idx / CHUNK_SIZE) as f32 / CHUNK_SIZE as f32;
+        // clipped to sea level like genchunk
+        *h = apply_regs(*h, regs, nx, ny).max(SEA_LEVEL);
+    }
+    cdata
+}
+
 /// create a slopemap from hightmap
 pub fn genslope(data: &ChunkData<f32>) -> ChunkData<f32> {
     // todo, use mabey uninit
@@ -207,6 +358,7 @@ pub fn genslope(data: &ChunkData<f32>) -> ChunkData<f32> {
 
 
 /// generate a chunks texture, projected with (u, v) = (x, z)
+/// this is the cpu version of the terrain material, for use without a gpu.
 // TODO consolidate arguments
 pub fn chunktotexture(
     data:&ChunkData<f32>, 
@@ -269,15 +421,15 @@ pub fn chunktotexture(
                  // compute texture index
                  let gidx = ((x%ASSET_SIZE)+(y%ASSET_SIZE)*ASSET_SIZE)*4;
                  
-                 if ih > 22.0 {
+                 if ih > SNOW_HEIGHT {
                     return [snow.data[gidx + 0],snow.data[gidx + 1],snow.data[gidx + 2],255]
-                 } else if ih > -0.3 {
-                    if slopedata[point_] > 1.5 {
+                 } else if ih > GRASS_HEIGHT {
+                    if slopedata[point_] > STONE_SLOPE {
                          return[stone.data[gidx + 0],stone.data[gidx + 0],stone.data[gidx + 0],255]
                     } else {
                         return [grass.data[gidx + 0],grass.data[gidx + 1],grass.data[gidx + 2],255]
                     }
-                 } else if ih> -0.7 {
+                 } else if ih> SAND_HEIGHT {
                     return [sand.data[gidx + 0],sand.data[gidx + 1],sand.data[gidx + 2],255]
                  } else {
                      return [water.data[gidx + 0],water.data[gidx + 1],water.data[gidx + 2],255]
@@ -303,6 +455,7 @@ pub fn chunktomesh(hightmap: &ChunkData<f32>) -> Mesh {
     
     // uvs control how a texture is maped onto the mesh.
     // this will strech the entire texture over the mesh, resulting in distortion on slopes
+    // only the debug views use these, the terrain material projects textures from the world position
     let mut uvs: Vec<[f32; 2]> = Vec::new();
     for i in 0..(CHUNK_SQSIZE) {
         let x = i % CHUNK_SIZE;
@@ -390,8 +543,78 @@ pub fn chunktomesh(hightmap: &ChunkData<f32>) -> Mesh {
     mesh
 }
 
+/// generate the hightmap of a chunk, world is the world seed, see world_origin
+pub fn genhightmap(seed: (f32,f32), world: u32) -> ChunkData<f32> {
+    let seed = world_chunk(seed, world);
+    genchunk(seed, &genchunkregs(seed))
+}
+
+/// the ground under the water of a chunk, genhightmap without the clip to sea level
+pub fn genseabedmap(seed: (f32,f32), world: u32) -> ChunkData<f32> {
+    let seed = world_chunk(seed, world);
+    genseabed(seed, &genchunkregs(seed))
+}
+
+/// genhightmap for many chunks at once, with the batch path and spread over all cores
+pub fn genhightmaps(seeds: &[(f32,f32)], world: u32) -> Vec<ChunkData<f32>> {
+    use rayon::prelude::*;
+    seeds.par_iter().map(|seed| {
+        let seed = world_chunk(*seed, world);
+        genchunk_batch(seed, &genchunkregs(seed))
+    }).collect()
+}
+
+#[test]
+fn batch_matches_scalar() {
+    for world in [0, 1, 77] {
+        let seeds: Vec<(f32,f32)> = (-2..2).flat_map(|x| (-2..2).map(move |z| (x as f32 * 7.0, z as f32 * 5.0))).collect();
+        let batch = genhightmaps(&seeds, world);
+        let mut stepped = 0;
+        for (seed, b) in seeds.iter().zip(batch) {
+            let seed = world_chunk(*seed, world);
+            let ox = seed.0*(CHUNK_SIZE-1) as f32;
+            let oy = seed.1*(CHUNK_SIZE-1) as f32;
+            let base = base_hightmap_batch(ox, oy);
+            for (i, h) in base.iter().enumerate() {
+                let scalar = get_base_hightmap(ox + (i % CHUNK_SIZE) as f32, oy + (i / CHUNK_SIZE) as f32);
+                assert!((h - scalar).abs() < BATCH_TOLERANCE, "{} and {} at {:?} {}", h, scalar, seed, i);
+            }
+            let scalar = genchunk(seed, &genchunkregs(seed));
+            stepped += b.iter().zip(scalar.iter()).filter(|(a, b)| (*a - *b).abs() >= BATCH_TOLERANCE).count();
+        }
+        // only the odd point right at a step
+        assert!(stepped < seeds.len() * CHUNK_SQSIZE / 1000, "{} points differ", stepped);
+    }
+}
+
+/// raw hightmaps of chunks, generated when they are first needed
+/// for planning things bigger than a chunk without waiting for chunks to load
+pub struct HeightCache {
+    world: u32,
+    chunks: std::collections::HashMap<(i32, i32), ChunkData<f32>>,
+}
+
+impl HeightCache {
+    pub fn new(world: u32) -> HeightCache {
+        HeightCache {
+            world,
+            chunks: Default::default(),
+        }
+    }
+
+    /// the raw hight at a world point, points are counted from the corner of chunk (0, 0)
+    pub fn get(&mut self, p: (i32, i32)) -> f32 {
+        let n = (CHUNK_SIZE - 1) as i32;
+        let c = (p.0.div_euclid(n), p.1.div_euclid(n));
+        let world = self.world;
+        let h = self.chunks.entry(c).or_insert_with(|| genhightmap((c.0 as f32, c.1 as f32), world));
+        h[p.0.rem_euclid(n) as usize + p.1.rem_euclid(n) as usize * CHUNK_SIZE]
+    }
+}
+
 /// helper function to generate textures and mesh, fails if assets are not loaded.
-pub fn gen(assets: &mut Assets<Image>,seed: (f32,f32)) -> Result<(Image,Mesh,ChunkData<f32>),String> {
+/// world is the world seed, see world_origin
+pub fn gen(assets: &mut Assets<Image>,seed: (f32,f32),world: u32) -> Result<(Image,Mesh,ChunkData<f32>),String> {
     // grab assets from ecs
     let grass = assets.get_handle(ASSETS_GRASS);
     let water = assets.get_handle(ASSETS_WATER);
@@ -405,12 +628,11 @@ pub fn gen(assets: &mut Assets<Image>,seed: (f32,f32)) -> Result<(Image,Mesh,Chu
     let snow = assets.get(&snow).map_or_else(|| Err("cant get asset snow.".to_string()), |x| Ok(x))?;
     let stone = assets.get(&stone).map_or_else(|| Err("cant get asset stone.".to_string()), |x| Ok(x))?;
     
-    // generate region data, this could be optimized
-    let reg   = genchunkreg(seed);
-    let regx  = genchunkreg((seed.0 + 1.0, seed.1 + 0.0));
-    let regy  = genchunkreg((seed.0 + 0.0, seed.1 + 1.0));
-    let regxy = genchunkreg((seed.0 + 1.0, seed.1 + 1.0));
-    let regs = [reg,regx,regy,regxy];
+    // move the chunk to the part of the world picked by the world seed
+    let seed = world_chunk(seed, world);
+    
+    // generate region data
+    let regs = genchunkregs(seed);
     // generate the hightmap
     let hightmap = genchunk(seed,&regs);
     // compute slope
@@ -0,0 +1,161 @@
+//! the terrain material, blends tiling textures on the gpu by hight and slope
+
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_resource::{
+    AddressMode, AsBindGroup, FilterMode, SamplerDescriptor, ShaderRef, TextureFormat,
+};
+use bevy::render::texture::ImageSampler;
+use crate::input::{Action, ActionState};
+use crate::map;
+
+/// the terrain shader, relative to the assets folder
+pub const TERRAIN_SHADER: &str = "shaders/terrain.wgsl";
+
+/// the tile textures in the order of the layers of the texture array
+pub const LAYERS: [&str; 5] = [
+    map::ASSETS_GRASS,
+    map::ASSETS_WATER,
+    map::ASSETS_SAND,
+    map::ASSETS_SNOW,
+    map::ASSETS_STONE,
+];
+
+pub use params::TerrainParams;
+
+// the ShaderType derive generates size checks next to the struct that are never called
+#[allow(dead_code)]
+mod params {
+    use bevy::render::render_resource::ShaderType;
+
+    /// the thresholds used to pick textures, hights are in hightmap units (before VOXEL_SCALE)
+    #[derive(ShaderType, Debug, Clone, Copy)]
+    pub struct TerrainParams {
+        /// snow above this
+        pub snow_height: f32,
+        /// grass (or stone) above this, sand below
+        pub grass_height: f32,
+        /// sand above this, water below
+        pub sand_height: f32,
+        /// stone where the slope is over this
+        pub stone_slope: f32,
+        /// half the width of the blend between hight bands
+        pub height_blend: f32,
+        /// half the width of the blend between grass and stone
+        pub slope_blend: f32,
+        /// the side length of a texture tile in world units
+        pub tile_size: f32,
+        pub voxel_scale: f32,
+        /// 1 to project textures along all three axes so cliffs are not stretched, 0 for the old (x, z) mapping
+        pub triplanar: u32,
+    }
+}
+
+impl Default for TerrainParams {
+    fn default() -> TerrainParams {
+        TerrainParams {
+            snow_height: map::SNOW_HEIGHT,
+            grass_height: map::GRASS_HEIGHT,
+            sand_height: map::SAND_HEIGHT,
+            stone_slope: map::STONE_SLOPE,
+            height_blend: 0.2,
+            slope_blend: 0.3,
+            // the baked texture had one tile per 4 points
+            tile_size: (map::ASSET_SIZE / map::PIXELS_PER_POINT) as f32 * map::VOXEL_SCALE,
+            voxel_scale: map::VOXEL_SCALE,
+            triplanar: 1,
+        }
+    }
+}
+
+/// a material for chunk meshes, all chunks share one
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "5f0d7c3e-6a1b-4e52-9a43-0c1f7e2b9d61"]
+pub struct TerrainMaterial {
+    #[uniform(0)]
+    pub params: TerrainParams,
+    /// the tile textures stacked in LAYERS order
+    #[texture(1, dimension = "2d_array")]
+    #[sampler(2)]
+    pub layers: Handle<Image>,
+}
+
+impl Material for TerrainMaterial {
+    fn fragment_shader() -> ShaderRef {
+        TERRAIN_SHADER.into()
+    }
+}
+
+/// the tile textures while they load, and the material once they have
+#[derive(Resource)]
+pub struct TerrainAssets {
+    tiles: Vec<Handle<Image>>,
+    pub material: Option<Handle<TerrainMaterial>>,
+}
+
+/// start loading the tile textures
+pub fn init(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    commands.insert_resource(TerrainAssets {
+        tiles: LAYERS.iter().map(|p| asset_server.load(*p)).collect(),
+        material: None,
+    });
+}
+
+/// stack the tiles into one texture array
+fn stack(tiles: &[&Image]) -> Option<Image> {
+    let mut data = Vec::new();
+    for t in tiles {
+        data.extend(t.convert(TextureFormat::Rgba8UnormSrgb)?.data);
+    }
+    let mut image = tiles[0].convert(TextureFormat::Rgba8UnormSrgb)?;
+    image.texture_descriptor.size.height *= tiles.len() as u32;
+    image.data = data;
+    image.reinterpret_stacked_2d_as_array(tiles.len() as u32);
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..Default::default()
+    });
+    Some(image)
+}
+
+/// create the material once all the tiles are loaded
+pub fn build(
+    mut assets: ResMut<TerrainAssets>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    if assets.material.is_some() {
+        return;
+    }
+    let tiles: Option<Vec<&Image>> = assets.tiles.iter().map(|h| images.get(h)).collect();
+    let layers = match tiles.and_then(|t| stack(&t)) {
+        Some(l) => l,
+        None => return
+    };
+    let layers = images.add(layers);
+    assets.material = Some(materials.add(TerrainMaterial {
+        params: TerrainParams::default(),
+        layers,
+    }));
+}
+
+/// switch between triplanar and planar texture mapping, for comparing them
+pub fn toggle_triplanar(
+    state: Res<ActionState>,
+    assets: Res<TerrainAssets>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    if !state.just_pressed(Action::ToggleTriplanar) {
+        return;
+    }
+    if let Some(m) = assets.material.as_ref().and_then(|h| materials.get_mut(h)) {
+        m.params.triplanar = 1 - m.params.triplanar;
+        println!("triplanar mapping {}", if m.params.triplanar == 1 {"on"} else {"off"});
+    }
+}
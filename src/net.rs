@@ -0,0 +1,504 @@
+//! streaming chunks from an authoritative server, instead of every client generating its own
+//! messages are framed as a little endian u32 length, a kind byte and the payload:
+//! - hello (0), server to client when it connects: the world seed
+//! - request (1), client to server: a chunk seed
+//! - hights (2), server to client: a chunk seed then the chunk as Packed::to_bytes
+//! - edit (3), client to server and then server to every client: world points and their new hights, at most MAX_EDIT_RADIUS from their middle
+//!
+//! edits are in world points so points on chunk edges change in every chunk that shares them.
+//! the server keeps every chunk that has been edited, so clients that ask later get the edited hights, and generates the rest again.
+//! edits are applied and queued for every client in one go, so every client gets them in the order the server applied them.
+
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use crate::chunk::{Map, WorldSeed};
+use crate::console::Command;
+use crate::export::Chunk;
+use crate::heightmap::Heightmap;
+use crate::lru::Lru;
+use crate::map::{ChunkData, CHUNK_SIZE, VOXEL_SCALE};
+use crate::packed::Packed;
+use crate::poi::Pois;
+
+/// messages bigger than this are refused, a chunk is far smaller
+const MAX_MESSAGE: usize = 1 << 24;
+/// the furthest an edit reaches from its middle, in points, the server refuses edits spread wider than this
+pub const MAX_EDIT_RADIUS: i32 = 32;
+/// messages waiting to be written to a client, a client further behind than this is dropped
+const QUEUED: usize = 256;
+/// unedited chunks the server keeps, the rest are generated again when asked for
+const GENERATED_CHUNKS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hello { world: u32 },
+    Request { seed: (i32, i32) },
+    /// unpacked hights are within Packed::max_error of the server's
+    Hights { seed: (i32, i32), hights: Packed },
+    Edit { points: Vec<((i32, i32), f32)> },
+}
+
+/// the chunks a world point is in, more than one on chunk edges
+pub fn chunks_of(point: (i32, i32)) -> Vec<(i32, i32)> {
+    let n = CHUNK_SIZE as i32 - 1;
+    let axis = |p: i32| {
+        let c = p.div_euclid(n);
+        // the first point of a chunk is also the last of the one before
+        if p.rem_euclid(n) == 0 {vec![c - 1, c]} else {vec![c]}
+    };
+    axis(point.0).into_iter().flat_map(|x| axis(point.1).into_iter().map(move |z| (x, z))).collect()
+}
+
+/// set the points of an edit that are in a chunk, returns if any were
+pub fn apply(hights: &mut ChunkData<f32>, seed: (i32, i32), points: &[((i32, i32), f32)]) -> bool {
+    let n = CHUNK_SIZE as i32 - 1;
+    let mut changed = false;
+    for ((x, z), h) in points {
+        let (lx, lz) = (x - seed.0 * n, z - seed.1 * n);
+        if (0..=n).contains(&lx) && (0..=n).contains(&lz) {
+            hights[(lx + lz * CHUNK_SIZE as i32) as usize] = *h;
+            changed = true;
+        }
+    }
+    changed
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let pair = |out: &mut Vec<u8>, p: (i32, i32)| {
+            out.extend(p.0.to_le_bytes());
+            out.extend(p.1.to_le_bytes());
+        };
+        match self {
+            Message::Hello { world } => {
+                out.push(0);
+                out.extend(world.to_le_bytes());
+            }
+            Message::Request { seed } => {
+                out.push(1);
+                pair(&mut out, *seed);
+            }
+            Message::Hights { seed, hights } => {
+                out.push(2);
+                pair(&mut out, *seed);
+                out.extend(hights.to_bytes());
+            }
+            Message::Edit { points } => {
+                out.push(3);
+                out.extend((points.len() as u32).to_le_bytes());
+                for (p, h) in points {
+                    pair(&mut out, *p);
+                    out.extend(h.to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Message, String> {
+        let short = || "message too short".to_string();
+        let word = |i: usize| bytes.get(i..i + 4).map(|b| [b[0], b[1], b[2], b[3]]).ok_or_else(short);
+        let int = |i: usize| word(i).map(i32::from_le_bytes);
+        let float = |i: usize| word(i).map(f32::from_le_bytes);
+        match bytes.first() {
+            Some(0) => Ok(Message::Hello { world: u32::from_le_bytes(word(1)?) }),
+            Some(1) => Ok(Message::Request { seed: (int(1)?, int(5)?) }),
+            Some(2) => {
+                let hights = Packed::from_bytes(bytes.get(9..).ok_or_else(short)?)?;
+                Ok(Message::Hights { seed: (int(1)?, int(5)?), hights })
+            }
+            Some(3) => {
+                let count = u32::from_le_bytes(word(1)?) as usize;
+                let points = (0..count)
+                    .map(|i| 5 + i * 12)
+                    .map(|o| Ok(((int(o)?, int(o + 4)?), float(o + 8)?)))
+                    .collect::<Result<_, String>>()?;
+                Ok(Message::Edit { points })
+            }
+            Some(k) => Err(format!("unknown message kind {}", k)),
+            None => Err(short()),
+        }
+    }
+}
+
+pub fn write_message(out: &mut impl Write, message: &Message) -> Result<(), String> {
+    let bytes = message.encode();
+    out.write_all(&(bytes.len() as u32).to_le_bytes()).map_err(|e| e.to_string())?;
+    out.write_all(&bytes).map_err(|e| e.to_string())?;
+    out.flush().map_err(|e| e.to_string())
+}
+
+pub fn read_message(input: &mut impl Read) -> Result<Message, String> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE {
+        return Err(format!("message of {} bytes is too big", len));
+    }
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+    Message::decode(&bytes)
+}
+
+/// a connected client, messages to it are written by a thread of its own so a slow client does not hold up the others
+struct Outgoing {
+    queue: SyncSender<Message>,
+    stream: TcpStream,
+}
+
+impl Outgoing {
+    fn new(stream: TcpStream) -> Result<Outgoing, String> {
+        let mut output = stream.try_clone().map_err(|e| e.to_string())?;
+        let (queue, messages) = sync_channel::<Message>(QUEUED);
+        std::thread::spawn(move || {
+            for m in messages {
+                if write_message(&mut output, &m).is_err() {
+                    // so the reading side ends too
+                    let _ = output.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+        Ok(Outgoing {queue, stream})
+    }
+
+    /// queue a message without waiting, a client that has gone or is too far behind is disconnected
+    fn send(&self, message: Message) -> bool {
+        if self.queue.try_send(message).is_ok() {
+            return true;
+        }
+        let _ = self.stream.shutdown(Shutdown::Both);
+        false
+    }
+}
+
+/// the hights of a chunk before edits, shared with the threads that send it
+type Generated = Arc<ChunkData<f32>>;
+
+/// the authoritative copy of the terrain
+pub struct Server {
+    pub world: u32,
+    pub import: Option<Heightmap>,
+    /// every chunk that has been edited, with its edits
+    /// locked while an edit is applied and sent, and while hights are queued, so nothing is sent out of order
+    edited: Mutex<HashMap<(i32, i32), Packed>>,
+    /// recently generated chunks, before edits
+    generated: Mutex<Lru<(i32, i32), Generated>>,
+    /// shared so poi regions are only worked out once
+    pois: Mutex<Pois>,
+    clients: Mutex<Vec<Arc<Outgoing>>>,
+}
+
+impl Server {
+    pub fn new(world: u32, import: Option<Heightmap>) -> Server {
+        Server {
+            world,
+            import,
+            edited: Mutex::new(HashMap::new()),
+            generated: Mutex::new(Lru::new(GENERATED_CHUNKS)),
+            pois: Mutex::new(Pois::default()),
+            clients: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// the hights of a chunk before edits
+    fn generated(&self, seed: (i32, i32)) -> Generated {
+        if let Some(hights) = self.generated.lock().unwrap().get(&seed) {
+            return hights;
+        }
+        // generated without holding a lock, so other clients are not held up
+        let mut pois = self.pois.lock().unwrap().around(self.world, (seed.0 as f32, seed.1 as f32));
+        let hights = Arc::new(Chunk::generate(self.world, seed, self.import.as_ref(), &mut pois).hightmap);
+        self.generated.lock().unwrap().insert(seed, hights.clone());
+        hights
+    }
+
+    /// the current hights of a chunk
+    pub fn hights(&self, seed: (i32, i32)) -> ChunkData<f32> {
+        let generated = self.generated(seed);
+        match self.edited.lock().unwrap().get(&seed) {
+            Some(p) => p.unpack().unwrap(),
+            None => (*generated).clone(),
+        }
+    }
+
+    /// apply an edit and send it to every client, edits wider than MAX_EDIT_RADIUS are refused
+    pub fn edit(&self, points: Vec<((i32, i32), f32)>) -> Result<(), String> {
+        let size = MAX_EDIT_RADIUS * 2 + 1;
+        if points.len() > (size * size) as usize {
+            return Err(format!("edit of {} points is too big", points.len()));
+        }
+        let wide = |f: fn(&(i32, i32)) -> i32| {
+            let (min, max) = points.iter().map(|(p, _)| f(p)).fold((i32::MAX, i32::MIN), |(a, b), v| (a.min(v), b.max(v)));
+            max.saturating_sub(min) >= size
+        };
+        if wide(|p| p.0) || wide(|p| p.1) {
+            return Err(format!("edit is spread over more than {} points", size));
+        }
+        let seeds: HashSet<(i32, i32)> = points.iter().flat_map(|(p, _)| chunks_of(*p)).collect();
+        // generated before taking the lock
+        let generated: Vec<_> = seeds.into_iter().map(|seed| (seed, self.generated(seed))).collect();
+        let mut edited = self.edited.lock().unwrap();
+        for (seed, hights) in generated {
+            let mut hights = edited.get(&seed).map_or_else(|| (*hights).clone(), |p| p.unpack().unwrap());
+            apply(&mut hights, seed, &points);
+            edited.insert(seed, Packed::pack(&hights));
+        }
+        let message = Message::Edit { points };
+        // queued before the edit lock is let go, clients that have gone or fallen behind are dropped
+        self.clients.lock().unwrap().retain(|c| c.send(message.clone()));
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> Result<(), String> {
+        let mut input = stream.try_clone().map_err(|e| e.to_string())?;
+        let output = Arc::new(Outgoing::new(stream)?);
+        output.send(Message::Hello { world: self.world });
+        self.clients.lock().unwrap().push(output.clone());
+        loop {
+            match read_message(&mut input)? {
+                Message::Request { seed } => {
+                    let generated = self.generated(seed);
+                    // under the edit lock, so an edit is either in these hights or sent after them
+                    let edited = self.edited.lock().unwrap();
+                    let hights = edited.get(&seed).cloned().unwrap_or_else(|| Packed::pack(&generated));
+                    if !output.send(Message::Hights { seed, hights }) {
+                        return Err("too far behind".to_string());
+                    }
+                }
+                Message::Edit { points } => if let Err(e) = self.edit(points) {
+                    // the client stays connected, it only loses the edit
+                    eprintln!("edit refused: {}", e);
+                },
+                m => return Err(format!("clients cant send {:?}", m)),
+            }
+        }
+    }
+
+    /// answer clients until the listener fails, each client gets its own thread
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), String> {
+        for stream in listener.incoming() {
+            let stream = stream.map_err(|e| e.to_string())?;
+            let server = self.clone();
+            std::thread::spawn(move || {
+                // a client going away ends up here too
+                if let Err(e) = server.handle(stream) {
+                    eprintln!("client left: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// a connection to a server, messages from it are read by a background thread
+pub struct Client {
+    stream: TcpStream,
+    incoming: Mutex<Receiver<Message>>,
+}
+
+impl Client {
+    pub fn connect(addr: &str) -> Result<Client, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("cant connect to {}: {}", addr, e))?;
+        let mut input = stream.try_clone().map_err(|e| e.to_string())?;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            while let Ok(m) = read_message(&mut input) {
+                if tx.send(m).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Client {stream, incoming: Mutex::new(rx)})
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), String> {
+        write_message(&mut self.stream, message)
+    }
+
+    /// every message that has arrived, without waiting
+    pub fn poll(&self) -> Vec<Message> {
+        self.incoming.lock().unwrap().try_iter().collect()
+    }
+
+    /// wait for the next message
+    pub fn wait(&self, timeout: std::time::Duration) -> Option<Message> {
+        self.incoming.lock().unwrap().recv_timeout(timeout).ok()
+    }
+}
+
+/// insert this to get chunks from a server, chunk::generate_maps then waits for them instead of generating
+#[derive(Resource)]
+pub struct Remote {
+    client: Client,
+    /// chunks as the server packed them, kept packed since they are kept after they are unloaded
+    chunks: HashMap<(i32, i32), Packed>,
+    /// chunks that have been edited since they arrived, unpacked so edits do not add up quantization error
+    edited: HashMap<(i32, i32), ChunkData<f32>>,
+    requested: HashSet<(i32, i32)>,
+}
+
+impl Remote {
+    pub fn connect(addr: &str) -> Result<Remote, String> {
+        Ok(Remote {client: Client::connect(addr)?, chunks: HashMap::new(), edited: HashMap::new(), requested: HashSet::new()})
+    }
+
+    /// if the hights of a chunk have arrived, asking the server for them the first time
+    pub fn has(&mut self, seed: (i32, i32)) -> bool {
+        if !self.chunks.contains_key(&seed) && self.requested.insert(seed) {
+            if let Err(e) = self.client.send(&Message::Request { seed }) {
+                println!("cant request chunk: {}", e);
+            }
+        }
+        self.chunks.contains_key(&seed)
+    }
+
+    pub fn hights(&self, seed: (i32, i32)) -> Option<ChunkData<f32>> {
+        match self.edited.get(&seed) {
+            Some(h) => Some(h.clone()),
+            None => self.chunks.get(&seed).and_then(|p| p.unpack().ok()),
+        }
+    }
+}
+
+/// handle messages from the server, edited chunks are regenerated
+pub fn receive(
+    mut commands: Commands,
+    remote: Option<ResMut<Remote>>,
+    mut world: ResMut<WorldSeed>,
+    mut maps: Query<&mut Map>,
+) {
+    let mut remote = match remote {
+        Some(r) => r,
+        None => return,
+    };
+    for m in remote.client.poll() {
+        match m {
+            Message::Hello { world: w } => if world.0 != w {
+                world.0 = w;
+            },
+            Message::Hights { seed, hights } => {
+                // the server's hights have its edits in them already
+                remote.edited.remove(&seed);
+                remote.chunks.insert(seed, hights);
+            }
+            Message::Edit { points } => {
+                let seeds: HashSet<(i32, i32)> = points.iter().flat_map(|(p, _)| chunks_of(*p)).collect();
+                for seed in seeds.iter() {
+                    if let Some(mut h) = remote.hights(*seed) {
+                        apply(&mut h, *seed, &points);
+                        remote.edited.insert(*seed, h);
+                    }
+                }
+                for mut map in maps.iter_mut() {
+                    if !seeds.contains(&(map.seed.0 as i32, map.seed.1 as i32)) || map.hightmap.is_none() {
+                        continue;
+                    }
+                    if let Some(r) = map.render.take() {
+                        commands.entity(r).despawn_recursive();
+                    }
+                    map.hightmap = None;
+                }
+            }
+            Message::Request { .. } => (),
+        }
+    }
+}
+
+/// the raise command, edits the ground arround the camera through the server
+pub fn commands(
+    mut commands: EventReader<Command>,
+    mut remote: Option<ResMut<Remote>>,
+    cameras: Query<&Transform, With<Camera>>,
+) {
+    for c in commands.iter() {
+        let (height, radius) = match c {
+            Command::Raise(h, r) => (*h, *r),
+            _ => continue,
+        };
+        let remote = match remote.as_mut() {
+            Some(r) => r,
+            None => {
+                println!("raise: only works when connected to a server");
+                continue;
+            }
+        };
+        if radius > MAX_EDIT_RADIUS as f32 {
+            println!("raise: the radius can be at most {}", MAX_EDIT_RADIUS);
+            continue;
+        }
+        let eye = cameras.iter().next().map_or(Vec3::ZERO, |c| c.translation) / VOXEL_SCALE;
+        let r = radius.ceil() as i32;
+        let (cx, cz) = (eye.x.round() as i32, eye.z.round() as i32);
+        let n = CHUNK_SIZE as i32 - 1;
+        let mut points = Vec::new();
+        let mut chunks = HashMap::new();
+        for z in cz - r..=cz + r {
+            for x in cx - r..=cx + r {
+                let d = Vec2::new((x - cx) as f32, (z - cz) as f32).length();
+                let seed = (x.div_euclid(n), z.div_euclid(n));
+                let current = match chunks.entry(seed).or_insert_with(|| remote.hights(seed)) {
+                    Some(h) if d <= radius => h[(x - seed.0 * n + (z - seed.1 * n) * CHUNK_SIZE as i32) as usize],
+                    _ => continue,
+                };
+                // falls off to nothing at the edge
+                points.push(((x, z), current + height * (1.0 - d / radius.max(1.0))));
+            }
+        }
+        if let Err(e) = remote.client.send(&Message::Edit { points }) {
+            println!("raise: {}", e);
+        }
+    }
+}
+
+#[test]
+fn clients_get_chunks_and_edits() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    std::thread::spawn(move || Arc::new(Server::new(5, None)).serve(listener));
+    let timeout = std::time::Duration::from_secs(30);
+
+    let mut a = Client::connect(&addr).unwrap();
+    let b = Client::connect(&addr).unwrap();
+    assert_eq!(a.wait(timeout), Some(Message::Hello { world: 5 }));
+    assert_eq!(b.wait(timeout), Some(Message::Hello { world: 5 }));
+
+    a.send(&Message::Request { seed: (0, 0) }).unwrap();
+    let generated = Chunk::generate(5, (0, 0), None, &mut Pois::default()).hightmap;
+    let packed = Packed::pack(&generated);
+    match a.wait(timeout) {
+        Some(Message::Hights { seed: (0, 0), hights }) => {
+            assert_eq!(hights, packed);
+            let hights = hights.unpack().unwrap();
+            let error = hights.iter().zip(generated.iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+            assert!(error <= packed.max_error());
+        }
+        m => panic!("expected hights but got {:?}", m),
+    }
+
+    // on the edge between chunk 0,0 and 1,0, so both get it
+    let edit = Message::Edit { points: vec![((63, 10), 42.0)] };
+    a.send(&edit).unwrap();
+    assert_eq!(a.wait(timeout), Some(edit.clone()));
+    assert_eq!(b.wait(timeout), Some(edit));
+    assert_eq!(chunks_of((63, 10)), vec![(0, 0), (1, 0)]);
+    a.send(&Message::Request { seed: (1, 0) }).unwrap();
+    match a.wait(timeout) {
+        Some(Message::Hights { seed: (1, 0), hights }) => assert!((hights.unpack().unwrap()[10 * CHUNK_SIZE] - 42.0).abs() < 0.01),
+        m => panic!("expected hights but got {:?}", m),
+    }
+
+    // edits reaching across the world are refused before anything is changed
+    let server = Server::new(5, None);
+    assert!(server.edit(vec![((0, 0), 1.0), ((100_000, 0), 1.0)]).is_err());
+    assert!(server.edited.lock().unwrap().is_empty());
+    assert!(server.edit(vec![((1, 1), 1.0), ((1 + MAX_EDIT_RADIUS * 2, 1), 1.0)]).is_ok());
+    // only the edited chunks are kept
+    assert_eq!(server.edited.lock().unwrap().len(), 2);
+    assert!((server.hights((1, 0))[2 + CHUNK_SIZE] - 1.0).abs() < 0.01);
+}
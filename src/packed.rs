@@ -0,0 +1,158 @@
+//! a compact form of a chunk's hights, for keeping chunks on disk, sending them and caching them once unloaded
+//! hights are quantized to 16 bits between the chunk's lowest and highest hight, each sample is stored as its
+//! difference from a prediction made from the samples left and above it, and the differences are deflated.
+//! neighbouring hights are close, so most differences are small and a chunk packs to about 6 KB instead of 16 KB,
+//! far less for flat chunks like open sea.
+
+use bevy::prelude::*;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use std::path::Path;
+use crate::map::{ChunkData, CHUNK_SIZE, CHUNK_SQSIZE};
+use crate::lru::Lru;
+
+/// how many unloaded chunks are kept by default, at a few KB each
+pub const CACHE_CHUNKS: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packed {
+    pub min: f32,
+    pub max: f32,
+    /// the deflated prediction errors, low bytes then high bytes
+    pub data: Vec<u8>,
+}
+
+/// the hight at x, z guessed from its neighbours, the median of left, above and left + above - above left
+fn predict(q: &[u16], x: usize, z: usize) -> i32 {
+    let at = |x: usize, z: usize| q[x + z * CHUNK_SIZE] as i32;
+    match (x, z) {
+        (0, 0) => 0,
+        (_, 0) => at(x - 1, 0),
+        (0, _) => at(0, z - 1),
+        _ => {
+            let (a, b, c) = (at(x - 1, z), at(x, z - 1), at(x - 1, z - 1));
+            (a + b - c).clamp(a.min(b), a.max(b))
+        }
+    }
+}
+
+impl Packed {
+    pub fn pack(hights: &ChunkData<f32>) -> Packed {
+        let min = hights.iter().cloned().fold(f32::MAX, f32::min);
+        let max = hights.iter().cloned().fold(f32::MIN, f32::max);
+        let range = (max - min).max(f32::EPSILON);
+        let q: Vec<u16> = hights.iter().map(|h| ((h - min) / range * 65535.0).round() as u16).collect();
+        // zigzag so small negative errors are small numbers too
+        let errors: Vec<u16> = (0..CHUNK_SQSIZE).map(|i| {
+            let e = (q[i] as i32 - predict(&q, i % CHUNK_SIZE, i / CHUNK_SIZE)) as i16;
+            ((e << 1) ^ (e >> 15)) as u16
+        }).collect();
+        let planes: Vec<u8> = errors.iter().map(|e| *e as u8).chain(errors.iter().map(|e| (e >> 8) as u8)).collect();
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::best());
+        deflate.write_all(&planes).unwrap();
+        Packed {min, max, data: deflate.finish().unwrap()}
+    }
+
+    pub fn unpack(&self) -> Result<ChunkData<f32>, String> {
+        let mut planes = Vec::new();
+        // a chunk's worth and one byte more, so corrupt data cant inflate to any size
+        DeflateDecoder::new(&self.data[..]).take(CHUNK_SQSIZE as u64 * 2 + 1).read_to_end(&mut planes).map_err(|e| format!("bad packed chunk: {}", e))?;
+        if planes.len() != CHUNK_SQSIZE * 2 {
+            return Err(format!("packed chunk has {} bytes instead of {}", planes.len(), CHUNK_SQSIZE * 2));
+        }
+        let mut q = vec![0u16; CHUNK_SQSIZE];
+        for i in 0..CHUNK_SQSIZE {
+            let e = planes[i] as u16 | (planes[i + CHUNK_SQSIZE] as u16) << 8;
+            let e = ((e >> 1) as i16) ^ -((e & 1) as i16);
+            q[i] = (predict(&q, i % CHUNK_SIZE, i / CHUNK_SIZE) + e as i32) as u16;
+        }
+        let mut hights: ChunkData<f32> = Box::new([0.0; CHUNK_SQSIZE]);
+        for (h, q) in hights.iter_mut().zip(q) {
+            *h = self.min + q as f32 / 65535.0 * (self.max - self.min);
+        }
+        Ok(hights)
+    }
+
+    /// the furthest an unpacked hight can be from the packed one, half a step plus f32 rounding
+    pub fn max_error(&self) -> f32 {
+        (self.max - self.min) / 65535.0 / 2.0 + (self.min.abs() + self.max.abs()) * f32::EPSILON
+    }
+
+    /// min and max as little endian f32, then the data
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.data.len() + 8);
+        out.extend(self.min.to_le_bytes());
+        out.extend(self.max.to_le_bytes());
+        out.extend(&self.data);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Packed, String> {
+        if bytes.len() < 8 {
+            return Err("packed chunk too short".to_string());
+        }
+        let float = |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Ok(Packed {min: float(0), max: float(4), data: bytes[8..].to_vec()})
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_bytes()).map_err(|e| format!("cant write {}: {}", path.display(), e))
+    }
+
+    pub fn read(path: &Path) -> Result<Packed, String> {
+        Packed::from_bytes(&std::fs::read(path).map_err(|e| format!("cant read {}: {}", path.display(), e))?)
+    }
+}
+
+/// the procedural hights of chunks that have been generated, so coming back to them is quick
+/// these are before roads are graded, roads can change while a chunk is unloaded
+#[derive(Resource)]
+pub struct Cache {
+    chunks: Lru<(u32, (i32, i32)), Packed>,
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache {chunks: Lru::new(CACHE_CHUNKS)}
+    }
+}
+
+impl Cache {
+    pub fn get(&mut self, world: u32, seed: (i32, i32)) -> Option<ChunkData<f32>> {
+        self.chunks.get(&(world, seed)).and_then(|p| p.unpack().ok())
+    }
+
+    pub fn insert(&mut self, world: u32, seed: (i32, i32), hights: &ChunkData<f32>) {
+        self.chunks.insert((world, seed), Packed::pack(hights));
+    }
+}
+
+#[test]
+fn packed_chunks_round_trip_closely() {
+    let mut pois = crate::poi::Pois::default();
+    for (world, seed) in [(0, (0, 0)), (3, (-5, 2)), (7, (1, 1)), (11, (40, -13))] {
+        let hights = crate::export::Chunk::generate(world, seed, None, &mut pois).hightmap;
+        let packed = Packed::from_bytes(&Packed::pack(&hights).to_bytes()).unwrap();
+        let unpacked = packed.unpack().unwrap();
+        let error = hights.iter().zip(unpacked.iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(error <= packed.max_error(), "error {} over {}", error, packed.max_error());
+        // smaller than the quantized hights alone
+        assert!(packed.to_bytes().len() < CHUNK_SQSIZE * 2, "{} bytes", packed.to_bytes().len());
+    }
+    // flat and very steep chunks
+    let flat: ChunkData<f32> = Box::new([2.5; CHUNK_SQSIZE]);
+    assert_eq!(Packed::pack(&flat).unpack().unwrap(), flat);
+    let mut steep: ChunkData<f32> = Box::new([0.0; CHUNK_SQSIZE]);
+    for (i, h) in steep.iter_mut().enumerate() {
+        *h = if (i / 7) % 2 == 0 {-1000.0} else {1000.0} + i as f32;
+    }
+    let packed = Packed::pack(&steep);
+    let error = steep.iter().zip(packed.unpack().unwrap().iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+    assert!(error <= packed.max_error());
+    // data that inflates to far more than a chunk is refused
+    let mut deflate = DeflateEncoder::new(Vec::new(), Compression::best());
+    deflate.write_all(&vec![0; CHUNK_SQSIZE * 64]).unwrap();
+    assert!(Packed {min: 0.0, max: 1.0, data: deflate.finish().unwrap()}.unpack().is_err());
+}
@@ -0,0 +1,72 @@
+//! colliders for chunks, so things can stand on the terrain, only built with the physics feature
+//! chunks without caves get a heightfield with the same triangles as chunktomesh, chunks with caves a trimesh of their mesh.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use bevy_rapier3d::prelude::*;
+use crate::chunk::Map;
+use crate::map::{self, ChunkData, CHUNK_SIZE, CHUNK_SQSIZE, VOXEL_SCALE};
+
+/// a heightfield collider for a hightmap, and where it goes relative to the chunk
+/// rapier centres heightfields on their origin, so it needs moving by half a chunk
+pub fn heightfield(hightmap: &ChunkData<f32>) -> (Collider, Vec3) {
+    // rapier wants the hights column major, with rows along z
+    let heights = (0..CHUNK_SQSIZE).map(|i| hightmap[i / CHUNK_SIZE + (i % CHUNK_SIZE) * CHUNK_SIZE]).collect();
+    let size = map::getchunksize();
+    // rapier splits cells along the same diagonal as chunktomesh, from (x, z + 1) to (x + 1, z)
+    let collider = Collider::heightfield(heights, CHUNK_SIZE, CHUNK_SIZE, Vec3::new(size, VOXEL_SCALE, size));
+    (collider, Vec3::new(size / 2.0, 0.0, size / 2.0))
+}
+
+/// a triangle mesh collider with the same triangles as a mesh, None if the mesh is empty or not a triangle list
+pub fn trimesh(mesh: &Mesh) -> Option<Collider> {
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION)? {
+        VertexAttributeValues::Float32x3(p) => p,
+        _ => return None
+    };
+    let indices = match mesh.indices()? {
+        Indices::U32(i) => i,
+        _ => return None
+    };
+    if indices.is_empty() {
+        return None;
+    }
+    Some(Collider::trimesh(
+        positions.iter().map(|p| Vec3::from(*p)).collect(),
+        indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect(),
+    ))
+}
+
+/// marks a chunk render entity that has had its collider added
+#[derive(Component)]
+pub struct HasCollider;
+
+/// give newly generated chunks a collider, as a child of the render entity so it unloads with it
+pub fn spawn(
+    mut commands: Commands,
+    maps: Query<&Map>,
+    done: Query<(), With<HasCollider>>,
+) {
+    for map in maps.iter() {
+        let (render, hightmap) = match (map.render, &map.hightmap) {
+            (Some(r), Some(h)) => (r, h),
+            _ => continue
+        };
+        if done.get(render).is_ok() {
+            continue;
+        }
+        // caves need every surface, not just the top one
+        let collider = if map.volume.is_some() {
+            map.mesh().and_then(trimesh).map(|c| (c, Vec3::ZERO))
+        } else {
+            Some(heightfield(hightmap))
+        };
+        let mut e = commands.entity(render);
+        e.insert(HasCollider);
+        if let Some((collider, offset)) = collider {
+            e.with_children(|c| {
+                c.spawn((collider, TransformBundle::from(Transform::from_translation(offset))));
+            });
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! points of interest: village, camp and landmark sites picked per region
+//! sites only depend on the world seed and the raw terrain, so they are the same however the world is explored.
+//! working out a region generates hightmaps for its candidate sites, in game that is done on the async compute pool
+//! and chunks wait for the regions they are flattened by, see Pois::ready.
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+use std::collections::{HashMap, HashSet};
+use crate::chunk::{Map, WorldSeed};
+use crate::console::Command;
+use crate::loader::ViewDistance;
+use crate::map::{self, ChunkData, HeightCache, CHUNK_SIZE, VOXEL_SCALE};
+
+/// a region is this many chunks along each side
+pub const REGION_CHUNKS: i32 = 4;
+/// points along the side of a region
+const REGION_POINTS: i32 = REGION_CHUNKS * (CHUNK_SIZE as i32 - 1);
+/// how many random sites are considered in each region
+const CANDIDATES: usize = 16;
+/// sites in one region are at least this far apart, in points
+const MIN_SPACING: f32 = 24.0;
+/// how far a village looks for water, in points
+const WATER_SEARCH: i32 = 64;
+/// the ground is blended back to its own hight over this distance outside a footprint, in points
+const SHOULDER: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoiKind {
+    /// flat and low, near water if possible
+    Village,
+    /// somewhere flat enough to pitch a tent
+    Camp,
+    /// a high point with a view
+    Landmark,
+}
+
+impl PoiKind {
+    /// the radius of the flattened footprint, in points
+    fn footprint(self) -> f32 {
+        match self {
+            PoiKind::Village => 8.0,
+            PoiKind::Camp => 3.0,
+            PoiKind::Landmark => 1.5,
+        }
+    }
+}
+
+/// a site, sent in a PoiFound event when chunks in its region first load
+#[derive(Debug, Clone, PartialEq)]
+pub struct Poi {
+    pub kind: PoiKind,
+    /// the centre of the footprint in world units, y is the flattened ground hight
+    pub position: Vec3,
+    /// the radius of the flattened footprint in world units
+    pub radius: f32,
+    /// the region it is in
+    pub region: (i32, i32),
+}
+
+/// sent once for each site when its region is first loaded
+pub struct PoiFound(pub Poi);
+
+/// the sites around the loaded part of the world, worked out a region at a time when needed
+#[derive(Resource, Default)]
+pub struct Pois {
+    world: u32,
+    regions: HashMap<(i32, i32), Vec<Poi>>,
+    /// regions being worked out in the background
+    planning: HashMap<(i32, i32), Task<Vec<Poi>>>,
+    /// regions whose sites have been sent as events
+    announced: HashSet<(i32, i32)>,
+    /// regions with generated chunks, waiting to be announced
+    to_announce: HashSet<(i32, i32)>,
+}
+
+/// the height differance across a circle of radius r, a measure of how flat a site is
+fn relief(heights: &mut HeightCache, p: (i32, i32), r: f32) -> f32 {
+    let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+    for i in 0..9 {
+        let (x, z) = if i == 0 {
+            p
+        } else {
+            let a = i as f32 * std::f32::consts::TAU / 8.0;
+            (p.0 + (a.cos() * r).round() as i32, p.1 + (a.sin() * r).round() as i32)
+        };
+        let h = heights.get((x, z));
+        lo = lo.min(h);
+        hi = hi.max(h);
+    }
+    hi - lo
+}
+
+/// how far away the nearest water is, in points, None if there is none within WATER_SEARCH
+fn water_distance(heights: &mut HeightCache, p: (i32, i32)) -> Option<i32> {
+    (1..=WATER_SEARCH / 8).map(|i| i * 8).find(|d| {
+        (0..8).any(|i| {
+            let a = i as f32 * std::f32::consts::TAU / 8.0;
+            heights.get((p.0 + (a.cos() * *d as f32) as i32, p.1 + (a.sin() * *d as f32) as i32)) <= map::SEA_LEVEL
+        })
+    })
+}
+
+/// how good a site is for a kind of poi, None if it is not suitable at all
+fn score(heights: &mut HeightCache, kind: PoiKind, p: (i32, i32)) -> Option<f32> {
+    let h = heights.get(p);
+    match kind {
+        PoiKind::Village => {
+            if !(map::GRASS_HEIGHT + 0.3..=10.0).contains(&h) {
+                return None;
+            }
+            let relief = relief(heights, p, kind.footprint());
+            // being near water is not required, but villages far from it score lower than any that are near
+            let water = water_distance(heights, p).map_or(WATER_SEARCH as f32 * 2.0, |d| d as f32);
+            (relief < 1.5).then_some(10.0 - relief * 3.0 - water / 16.0)
+        }
+        PoiKind::Camp => {
+            if !(map::GRASS_HEIGHT..=15.0).contains(&h) {
+                return None;
+            }
+            let relief = relief(heights, p, kind.footprint());
+            (relief < 1.5).then_some(5.0 - relief * 2.0)
+        }
+        PoiKind::Landmark => (h > 8.0).then_some(h),
+    }
+}
+
+/// work out the sites in a region, the same every time for a world
+pub fn region_pois(heights: &mut HeightCache, world: u32, region: (i32, i32)) -> Vec<Poi> {
+    let mut state = map::hash(map::hash(map::hash(world) ^ region.0 as u32) ^ region.1 as u32);
+    let mut next = || {
+        state = map::hash(state.wrapping_add(0x6d2b79f5));
+        state
+    };
+    let origin = (region.0 * REGION_POINTS, region.1 * REGION_POINTS);
+    let candidates: Vec<(i32, i32)> = (0..CANDIDATES)
+        .map(|_| (origin.0 + (next() % REGION_POINTS as u32) as i32, origin.1 + (next() % REGION_POINTS as u32) as i32))
+        .collect();
+
+    let mut found: Vec<Poi> = Vec::new();
+    for kind in [PoiKind::Village, PoiKind::Landmark, PoiKind::Camp] {
+        let best = candidates.iter()
+            .filter(|c| found.iter().all(|f| {
+                Vec2::new(c.0 as f32, c.1 as f32).distance(Vec2::new(f.position.x, f.position.z) / VOXEL_SCALE) > MIN_SPACING
+            }))
+            .filter_map(|c| score(heights, kind, *c).map(|s| (s, *c)))
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some((_, c)) = best {
+            let h = heights.get(c).max(map::SEA_LEVEL + 0.3);
+            found.push(Poi {
+                kind,
+                position: Vec3::new(c.0 as f32, h, c.1 as f32) * VOXEL_SCALE,
+                radius: kind.footprint() * VOXEL_SCALE,
+                region,
+            });
+        }
+    }
+    found
+}
+
+/// the region a chunk is in
+pub fn chunk_region(seed: (f32, f32)) -> (i32, i32) {
+    ((seed.0 as i32).div_euclid(REGION_CHUNKS), (seed.1 as i32).div_euclid(REGION_CHUNKS))
+}
+
+/// the regions with any part within distance (world units) of a position
+fn regions_near(position: Vec3, distance: f32) -> impl Iterator<Item = (i32, i32)> {
+    let size = REGION_POINTS as f32 * VOXEL_SCALE;
+    let lo = ((position.x - distance) / size).floor() as i32;
+    let hi = ((position.x + distance) / size).floor() as i32;
+    let lo_z = ((position.z - distance) / size).floor() as i32;
+    let hi_z = ((position.z + distance) / size).floor() as i32;
+    (lo..=hi).flat_map(move |x| (lo_z..=hi_z).map(move |z| (x, z)))
+}
+
+/// the middle of a chunk and how far from it a site can flatten the chunk, in world units
+fn flatten_reach(seed: (f32, f32)) -> (Vec3, f32) {
+    let n = (CHUNK_SIZE - 1) as f32;
+    let middle = Vec3::new((seed.0 + 0.5) * n, 0.0, (seed.1 + 0.5) * n) * VOXEL_SCALE;
+    // far enough to catch any footprint that reaches into the chunk
+    (middle, (n * std::f32::consts::FRAC_1_SQRT_2 + PoiKind::Village.footprint() + SHOULDER) * VOXEL_SCALE)
+}
+
+impl Pois {
+    /// everything is for one world, forget it all when the world changes
+    fn set_world(&mut self, world: u32) {
+        if world != self.world {
+            *self = Pois {world, ..Default::default()};
+        }
+    }
+
+    /// the sites in a region, working them out on this thread if needed
+    pub fn region(&mut self, world: u32, region: (i32, i32)) -> &[Poi] {
+        self.set_world(world);
+        let planned = self.planning.remove(&region);
+        self.regions.entry(region).or_insert_with(|| match planned {
+            Some(task) => future::block_on(task),
+            None => region_pois(&mut HeightCache::new(world), world, region),
+        })
+    }
+
+    /// whether a region is worked out, if not it is started in the background
+    /// only for use in game, it needs the async compute pool
+    fn poll(&mut self, world: u32, region: (i32, i32)) -> bool {
+        self.set_world(world);
+        if self.regions.contains_key(&region) {
+            return true;
+        }
+        let task = self.planning.entry(region).or_insert_with(|| {
+            AsyncComputeTaskPool::get().spawn(async move { region_pois(&mut HeightCache::new(world), world, region) })
+        });
+        match future::block_on(future::poll_once(task)) {
+            Some(found) => {
+                self.planning.remove(&region);
+                self.regions.insert(region, found);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// whether every region that can flatten a chunk is worked out, so flatten will not hold up the frame
+    /// regions that are not are started in the background
+    pub fn ready(&mut self, world: u32, seed: (f32, f32)) -> bool {
+        let (middle, reach) = flatten_reach(seed);
+        // no short circuit, so every missing region is started at once
+        regions_near(middle, reach).fold(true, |ready, r| self.poll(world, r) & ready)
+    }
+
+    /// the regions that can flatten a chunk, worked out here if needed, so the chunk can be generated without this
+    pub fn around(&mut self, world: u32, seed: (f32, f32)) -> Pois {
+        let (middle, reach) = flatten_reach(seed);
+        let mut out = Pois {world, ..Default::default()};
+        for r in regions_near(middle, reach) {
+            out.regions.insert(r, self.region(world, r).to_vec());
+        }
+        out
+    }
+
+    /// all sites within distance (world units) of a position
+    pub fn near(&mut self, world: u32, position: Vec3, distance: f32) -> Vec<Poi> {
+        let mut out = Vec::new();
+        for r in regions_near(position, distance) {
+            out.extend(self.region(world, r).iter()
+                .filter(|p| Vec2::new(p.position.x - position.x, p.position.z - position.z).length() <= distance)
+                .cloned());
+        }
+        out
+    }
+
+    /// flatten the footprints of sites in and arround a chunk, seed is the chunk seed before world_chunk
+    pub fn flatten(&mut self, world: u32, hightmap: &mut ChunkData<f32>, seed: (f32, f32)) {
+        let n = (CHUNK_SIZE - 1) as f32;
+        let (middle, reach) = flatten_reach(seed);
+        let pois = self.near(world, middle, reach);
+        for (i, h) in hightmap.iter_mut().enumerate() {
+            let p = Vec2::new(seed.0 * n + (i % CHUNK_SIZE) as f32, seed.1 * n + (i / CHUNK_SIZE) as f32);
+            for poi in pois.iter() {
+                let d = p.distance(Vec2::new(poi.position.x, poi.position.z) / VOXEL_SCALE);
+                let blend = 1.0 - ((d - poi.radius / VOXEL_SCALE) / SHOULDER).clamp(0.0, 1.0);
+                let blend = blend * blend * (3.0 - 2.0 * blend);
+                *h += (poi.position.y / VOXEL_SCALE - *h) * blend;
+            }
+        }
+    }
+}
+
+/// start working out the regions arround the camera before the loader gets to them
+pub fn prepare(
+    world: Res<WorldSeed>,
+    view: Res<ViewDistance>,
+    mut pois: ResMut<Pois>,
+    cameras: Query<&Transform, With<Camera>>,
+) {
+    if let Some(c) = cameras.iter().next() {
+        let ahead = view.radius() + REGION_POINTS as f32 * VOXEL_SCALE;
+        for r in regions_near(c.translation, ahead) {
+            pois.poll(world.0, r);
+        }
+    }
+}
+
+/// send PoiFound for the sites of each region the first time one of its chunks is generated
+/// chunks from a server or an imported heightmap do not wait for their region, so it is announced once it is worked out
+pub fn announce(
+    world: Res<WorldSeed>,
+    mut pois: ResMut<Pois>,
+    mut events: EventWriter<PoiFound>,
+    maps: Query<&Map, Changed<Map>>,
+) {
+    // poll resets everything when the world changes, so call it before anything else
+    pois.set_world(world.0);
+    for map in maps.iter() {
+        if map.hightmap.is_some() {
+            pois.to_announce.insert(chunk_region(map.seed));
+        }
+    }
+    let waiting: Vec<(i32, i32)> = pois.to_announce.iter().cloned().collect();
+    for region in waiting {
+        if !pois.poll(world.0, region) {
+            continue;
+        }
+        pois.to_announce.remove(&region);
+        if pois.announced.insert(region) {
+            for p in pois.regions[&region].clone() {
+                events.send(PoiFound(p));
+            }
+        }
+    }
+}
+
+/// print sites as they are found
+pub fn log_found(mut events: EventReader<PoiFound>) {
+    for PoiFound(p) in events.iter() {
+        println!("found a {:?} at {}", p.kind, p.position);
+    }
+}
+
+/// handle the pois console command
+pub fn commands(
+    mut commands: EventReader<Command>,
+    world: Res<WorldSeed>,
+    mut pois: ResMut<Pois>,
+    cameras: Query<&Transform, With<Camera>>,
+) {
+    for c in commands.iter() {
+        if *c == Command::ListPois {
+            let eye = cameras.iter().next().map_or(Vec3::ZERO, |c| c.translation);
+            for p in pois.near(world.0, eye, 300.0) {
+                println!("{:?} at {:.1} {:.1} {:.1}", p.kind, p.position.x, p.position.y, p.position.z);
+            }
+        }
+    }
+}
+
+#[test]
+fn pois_are_deterministic_and_flat() {
+    let mut heights = HeightCache::new(0);
+    let a = region_pois(&mut heights, 0, (0, 0));
+    assert_eq!(a, region_pois(&mut HeightCache::new(0), 0, (0, 0)));
+    let n = (CHUNK_SIZE - 1) as f32;
+    let mut pois = Pois::default();
+    for p in pois.near(0, Vec3::ZERO, 200.0) {
+        // flatten the chunk under the centre and check it is at the site hight
+        let x = (p.position.x / VOXEL_SCALE).round();
+        let z = (p.position.z / VOXEL_SCALE).round();
+        let seed = ((x / n).floor(), (z / n).floor());
+        let mut h = map::genhightmap(seed, 0);
+        pois.flatten(0, &mut h, seed);
+        let i = (x - seed.0 * n) as usize + (z - seed.1 * n) as usize * CHUNK_SIZE;
+        assert!((h[i] - p.position.y / VOXEL_SCALE).abs() < 0.01);
+    }
+}
@@ -0,0 +1,159 @@
+//! a z/x/y png tile pyramid of a range of chunks, for looking at a world with a slippy map viewer
+//! the deepest zoom has one tile per chunk, coloured like chunktotexture and hillshaded, every zoom above it
+//! is downsampled from the one below. tile 0/0/0 covers the whole range, with the first chunk in its top left corner.
+
+use bevy::prelude::Image;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use crate::map::{ChunkData, CHUNK_SIZE, PIXELS_PER_POINT};
+
+/// width and hight of a tile, in pixels
+pub const TILE_SIZE: u32 = 256;
+/// brightness of flat ground, light comes from the north west at 45 degrees
+const FLAT_SHADE: f32 = 0.577;
+/// how dark slopes facing away from the light get
+const MIN_SHADE: f32 = 0.35;
+
+/// the layout of a pyramid, written next to the tiles as tiles.ron
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pyramid {
+    /// inclusive chunk range
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+    /// the zoom with one tile per chunk
+    pub max_zoom: u32,
+    pub tile_size: u32,
+}
+
+impl Pyramid {
+    pub fn new(from: (i32, i32), to: (i32, i32)) -> Pyramid {
+        let chunks = (to.0 - from.0 + 1).max(to.1 - from.1 + 1) as u32;
+        Pyramid {
+            from,
+            to,
+            max_zoom: chunks.next_power_of_two().trailing_zeros(),
+            tile_size: TILE_SIZE,
+        }
+    }
+
+    /// the tile of a chunk at max_zoom
+    pub fn tile(&self, chunk: (i32, i32)) -> (u32, u32) {
+        ((chunk.0 - self.from.0) as u32, (chunk.1 - self.from.1) as u32)
+    }
+
+    /// how many tiles a zoom has across that are not empty
+    pub fn tiles(&self, zoom: u32) -> (u32, u32) {
+        let shift = self.max_zoom - zoom;
+        let (w, h) = self.tile(self.to);
+        ((w >> shift) + 1, (h >> shift) + 1)
+    }
+
+    pub fn path(dir: &Path, zoom: u32, tile: (u32, u32)) -> PathBuf {
+        dir.join(zoom.to_string()).join(tile.0.to_string()).join(format!("{}.png", tile.1))
+    }
+
+    /// make every zoom above max_zoom from the tiles already in dir, and write tiles.ron
+    pub fn downsample_all(&self, dir: &Path) -> Result<(), String> {
+        for zoom in (0..self.max_zoom).rev() {
+            let (w, h) = self.tiles(zoom);
+            for x in 0..w {
+                for y in 0..h {
+                    let children = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                        image::open(Pyramid::path(dir, zoom + 1, (x * 2 + dx, y * 2 + dy))).ok().map(|i| i.into_rgba8())
+                    });
+                    write_tile(&Pyramid::path(dir, zoom, (x, y)), &downsample(&children))?;
+                }
+            }
+        }
+        let s = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new()).unwrap();
+        std::fs::write(dir.join("tiles.ron"), s).map_err(|e| format!("cant write tiles.ron: {}", e))
+    }
+}
+
+/// how lit each point of a chunk is, 1.0 for flat ground
+fn shade(hightmap: &ChunkData<f32>) -> Vec<f32> {
+    let h = |x: usize, z: usize| hightmap[x.min(CHUNK_SIZE - 1) + z.min(CHUNK_SIZE - 1) * CHUNK_SIZE];
+    // one sided at the edges, so the difference is over one point rather than two
+    let span = |a: usize| ((a + 1).min(CHUNK_SIZE - 1) - a.saturating_sub(1)) as f32;
+    (0..CHUNK_SIZE * CHUNK_SIZE).map(|i| {
+        let (x, z) = (i % CHUNK_SIZE, i / CHUNK_SIZE);
+        let dx = (h(x + 1, z) - h(x.saturating_sub(1), z)) / span(x);
+        let dz = (h(x, z + 1) - h(x, z.saturating_sub(1))) / span(z);
+        // the normal is (-dx, 1, -dz) and the light (-1, 1, -1)
+        let lit = (dx + 1.0 + dz) / ((dx * dx + 1.0 + dz * dz).sqrt() * 3f32.sqrt());
+        (lit / FLAT_SHADE).max(MIN_SHADE)
+    }).collect()
+}
+
+/// the deepest zoom tile of a chunk, from its texture and hights
+/// chunks share their edge points, so the last point of the texture is left out and the rest stretched over the tile
+pub fn chunk_tile(texture: Image, hightmap: &ChunkData<f32>) -> Result<RgbaImage, String> {
+    let texture = texture.try_into_dynamic().map_err(|e| e.to_string())?.into_rgba8();
+    let shade = shade(hightmap);
+    let covered = ((CHUNK_SIZE - 1) * PIXELS_PER_POINT) as f32;
+    Ok(RgbaImage::from_fn(TILE_SIZE, TILE_SIZE, |x, y| {
+        let (tx, ty) = (x as f32 * covered / TILE_SIZE as f32, y as f32 * covered / TILE_SIZE as f32);
+        // shade between the points, so it does not look blocky
+        let (px, pz) = (tx / PIXELS_PER_POINT as f32, ty / PIXELS_PER_POINT as f32);
+        let (ix, iz) = ((px as usize).min(CHUNK_SIZE - 2), (pz as usize).min(CHUNK_SIZE - 2));
+        let s = |x: usize, z: usize| shade[x + z * CHUNK_SIZE];
+        // lerp gives its first argument at 1.0
+        let top = crate::lerp(s(ix + 1, iz), s(ix, iz), px - ix as f32);
+        let bottom = crate::lerp(s(ix + 1, iz + 1), s(ix, iz + 1), px - ix as f32);
+        let k = crate::lerp(bottom, top, pz - iz as f32);
+        let Rgba([r, g, b, a]) = *texture.get_pixel(tx as u32, ty as u32);
+        let lit = |c: u8| (c as f32 * k).min(255.0) as u8;
+        Rgba([lit(r), lit(g), lit(b), a])
+    }))
+}
+
+/// join four tiles at half size, in the order top left, top right, bottom left, bottom right
+/// missing tiles are left transparent
+pub fn downsample(children: &[Option<RgbaImage>; 4]) -> RgbaImage {
+    let half = TILE_SIZE / 2;
+    RgbaImage::from_fn(TILE_SIZE, TILE_SIZE, |x, y| {
+        let child = &children[(x / half + y / half * 2) as usize];
+        match child {
+            Some(c) => {
+                let (cx, cy) = (x % half * 2, y % half * 2);
+                let pixels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| c.get_pixel(cx + dx, cy + dy).0);
+                Rgba([0, 1, 2, 3].map(|i| (pixels.iter().map(|p| p[i] as u32).sum::<u32>() / 4) as u8))
+            }
+            None => Rgba([0, 0, 0, 0]),
+        }
+    })
+}
+
+/// write a tile, making its directories
+pub fn write_tile(path: &Path, tile: &RgbaImage) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cant create {}: {}", dir.display(), e))?;
+    }
+    tile.save(path).map_err(|e| format!("cant write {}: {}", path.display(), e))
+}
+
+#[test]
+fn pyramids_cover_the_range() {
+    let p = Pyramid::new((-2, 3), (2, 4));
+    assert_eq!(p.max_zoom, 3);
+    assert_eq!(p.tile((2, 4)), (4, 1));
+    assert_eq!(p.tiles(0), (1, 1));
+    assert_eq!(p.tiles(2), (3, 1));
+
+    let red = RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, Rgba([200, 0, 0, 255]));
+    let tile = downsample(&[Some(red.clone()), None, None, Some(red)]);
+    assert_eq!(tile.get_pixel(0, 0).0, [200, 0, 0, 255]);
+    assert_eq!(tile.get_pixel(TILE_SIZE - 1, 0).0, [0, 0, 0, 0]);
+    assert_eq!(tile.get_pixel(TILE_SIZE - 1, TILE_SIZE - 1).0, [200, 0, 0, 255]);
+
+    // flat ground is not shaded
+    assert!(shade(&Box::new([1.0; CHUNK_SIZE * CHUNK_SIZE])).iter().all(|s| (s - 1.0).abs() < 0.01));
+    // an even slope is shaded the same at the edges as inside
+    let mut slope = Box::new([0.0; CHUNK_SIZE * CHUNK_SIZE]);
+    for (i, h) in slope.iter_mut().enumerate() {
+        *h = (i % CHUNK_SIZE) as f32 * 0.5;
+    }
+    let shaded = shade(&slope);
+    assert!(shaded.iter().all(|s| (s - shaded[CHUNK_SIZE / 2]).abs() < 0.001));
+}
@@ -0,0 +1,303 @@
+//! roads between waypoints, found with A* over the terrain and graded into the hightmap
+//! roads are planned on the raw hightmap of the whole world, not on loaded chunks,
+//! so a road looks the same whatever order the chunks it crosses are generated in.
+//! planning a road can take seconds, so the road command plans it on the async compute pool and the road is added once it is done.
+
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use crate::chunk::WorldSeed;
+use crate::console::Command;
+use crate::map::{self, ChunkData, HeightCache, CHUNK_SIZE, VOXEL_SCALE};
+
+/// spacing of the A* grid, in points
+const STEP: i32 = 2;
+/// how far outside the box arround the ends a road may wander, in points
+const MARGIN: i32 = 96;
+/// how much climbing costs, higher makes roads wind arround hills more
+const SLOPE_COST: f32 = 40.0;
+/// extra cost per point of road under water, roads only cross water when they have to
+const WATER_COST: f32 = 50.0;
+/// A* gives up after visiting this many nodes
+const MAX_VISITED: usize = 400_000;
+/// how many nodes on either side are averaged to get the road hight
+const SMOOTH: usize = 4;
+/// half the width of the road surface, in points
+pub const HALF_WIDTH: f32 = 1.2;
+/// the ground is blended back to its own hight over this distance beyond the road, in points
+pub const SHOULDER: f32 = 3.0;
+
+/// a road, as a line through world points (x, hight, z), the hight is the graded road surface
+#[derive(Debug, Clone, PartialEq)]
+pub struct Road {
+    pub points: Vec<Vec3>,
+}
+
+impl Road {
+    /// the range of chunks (inclusive) whose hights the road changes
+    pub fn chunks(&self) -> ((i32, i32), (i32, i32)) {
+        let n = (CHUNK_SIZE - 1) as f32;
+        let reach = HALF_WIDTH + SHOULDER;
+        let lo = self.points.iter().fold(Vec2::splat(f32::MAX), |m, p| m.min(Vec2::new(p.x, p.z))) - reach;
+        let hi = self.points.iter().fold(Vec2::splat(f32::MIN), |m, p| m.max(Vec2::new(p.x, p.z))) + reach;
+        // chunk c covers points c * n to (c + 1) * n
+        let first = |v: f32| (v / n - 1.0).ceil() as i32;
+        let last = |v: f32| (v / n).floor() as i32;
+        ((first(lo.x), first(lo.y)), (last(hi.x), last(hi.y)))
+    }
+}
+
+/// all roads in the current world
+#[derive(Resource, Debug, Default)]
+pub struct Roads {
+    pub list: Vec<Road>,
+}
+
+/// sent when roads are added or removed, with the range of chunks (inclusive) that have to be generated again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoadChanged {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+}
+
+impl RoadChanged {
+    fn of(road: &Road) -> RoadChanged {
+        let (from, to) = road.chunks();
+        RoadChanged {from, to}
+    }
+}
+
+/// roads being planned, with the world they are for
+#[derive(Resource, Default)]
+pub struct Planning {
+    tasks: Vec<(u32, Task<Result<Road, String>>)>,
+}
+
+/// an A* node waiting to be visited, ordered so the BinaryHeap pops the lowest estimate first
+#[derive(PartialEq)]
+struct Open {
+    estimate: f32,
+    node: (i32, i32),
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// the cheapest path between two grid nodes, in world points
+fn astar(heights: &mut HeightCache, from: (i32, i32), to: (i32, i32)) -> Result<Vec<(i32, i32)>, String> {
+    let min = (from.0.min(to.0) - MARGIN, from.1.min(to.1) - MARGIN);
+    let max = (from.0.max(to.0) + MARGIN, from.1.max(to.1) + MARGIN);
+    let dist = |a: (i32, i32), b: (i32, i32)| (((a.0 - b.0).pow(2) + (a.1 - b.1).pow(2)) as f32).sqrt();
+
+    let mut open = BinaryHeap::new();
+    let mut cost: HashMap<(i32, i32), f32> = HashMap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    open.push(Open {estimate: dist(from, to), node: from});
+    cost.insert(from, 0.0);
+
+    while let Some(Open {node, ..}) = open.pop() {
+        if node == to {
+            let mut path = vec![to];
+            while let Some(p) = came_from.get(path.last().unwrap()) {
+                path.push(*p);
+            }
+            path.reverse();
+            return Ok(path);
+        }
+        if cost.len() > MAX_VISITED {
+            break;
+        }
+        let here = heights.get(node);
+        for (dx, dz) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let next = (node.0 + dx * STEP, node.1 + dz * STEP);
+            if next.0 < min.0 || next.1 < min.1 || next.0 > max.0 || next.1 > max.1 {
+                continue;
+            }
+            let h = heights.get(next);
+            let d = dist(node, next);
+            let grade = (h - here) / d;
+            let mut step = d * (1.0 + SLOPE_COST * grade * grade);
+            if h <= map::SEA_LEVEL {
+                step += d * WATER_COST;
+            }
+            let c = cost[&node] + step;
+            if cost.get(&next).is_none_or(|old| c < *old) {
+                cost.insert(next, c);
+                came_from.insert(next, node);
+                open.push(Open {estimate: c + dist(next, to), node: next});
+            }
+        }
+    }
+    Err(format!("no road found from {:?} to {:?}", from, to))
+}
+
+/// snap a world position (world units) to the A* grid
+fn to_grid(p: Vec2) -> (i32, i32) {
+    let snap = |x: f32| (x / VOXEL_SCALE / STEP as f32).round() as i32 * STEP;
+    (snap(p.x), snap(p.y))
+}
+
+/// plan a road through waypoints (world units, x and z) in a world
+/// this generates the hightmaps the road crosses and can take seconds, run it off the main thread in game
+pub fn plan(world: u32, waypoints: &[Vec2]) -> Result<Road, String> {
+    if waypoints.len() < 2 {
+        return Err("a road needs at least two waypoints".to_string());
+    }
+    let mut heights = HeightCache::new(world);
+    let mut path = vec![to_grid(waypoints[0])];
+    for pair in waypoints.windows(2) {
+        let leg = astar(&mut heights, to_grid(pair[0]), to_grid(pair[1]))?;
+        path.extend(leg.into_iter().skip(1));
+    }
+    let raw: Vec<f32> = path.iter().map(|p| heights.get(*p).max(map::SEA_LEVEL)).collect();
+    // average the hight along the road so it climbs evenly instead of following every bump
+    let points = path.iter().enumerate().map(|(i, p)| {
+        let window = &raw[i.saturating_sub(SMOOTH)..(i + SMOOTH + 1).min(raw.len())];
+        let h = window.iter().sum::<f32>() / window.len() as f32;
+        Vec3::new(p.0 as f32, h, p.1 as f32)
+    }).collect();
+    Ok(Road {points})
+}
+
+impl Roads {
+    /// plan a road and add it, on this thread
+    pub fn connect(&mut self, world: u32, waypoints: &[Vec2]) -> Result<&Road, String> {
+        self.list.push(plan(world, waypoints)?);
+        Ok(self.list.last().unwrap())
+    }
+
+    /// flatten the ground under any roads crossing a chunk, seed is the chunk seed before world_chunk
+    /// returns how much road covers each point (0.0 to 1.0), None if no road crosses the chunk
+    pub fn grade(&self, hightmap: &mut ChunkData<f32>, seed: (f32, f32)) -> Option<ChunkData<f32>> {
+        let n = (CHUNK_SIZE - 1) as f32;
+        let origin = Vec2::new(seed.0 * n, seed.1 * n);
+        let reach = HALF_WIDTH + SHOULDER;
+        // only segments near this chunk
+        let segments: Vec<(Vec3, Vec3)> = self.list.iter()
+            .flat_map(|r| r.points.windows(2).map(|w| (w[0], w[1])))
+            .filter(|(a, b)| {
+                let lo = a.min(*b);
+                let hi = a.max(*b);
+                lo.x - reach <= origin.x + n && hi.x + reach >= origin.x && lo.z - reach <= origin.y + n && hi.z + reach >= origin.y
+            })
+            .collect();
+        if segments.is_empty() {
+            return None;
+        }
+        let mut mask = Box::new([0.0_f32; map::CHUNK_SQSIZE]);
+        for (i, h) in hightmap.iter_mut().enumerate() {
+            let p = origin + Vec2::new((i % CHUNK_SIZE) as f32, (i / CHUNK_SIZE) as f32);
+            // the nearest road, and its hight there
+            let nearest = segments.iter().map(|(a, b)| {
+                let a2 = Vec2::new(a.x, a.z);
+                let ab = Vec2::new(b.x, b.z) - a2;
+                let t = ((p - a2).dot(ab) / ab.length_squared().max(0.0001)).clamp(0.0, 1.0);
+                ((a2 + ab * t).distance(p), a.y + (b.y - a.y) * t)
+            }).min_by(|x, y| x.0.total_cmp(&y.0)).unwrap();
+            let (d, road_h) = nearest;
+            if d > reach {
+                continue;
+            }
+            let blend = 1.0 - ((d - HALF_WIDTH) / SHOULDER).clamp(0.0, 1.0);
+            let blend = blend * blend * (3.0 - 2.0 * blend);
+            *h += (road_h - *h) * blend;
+            // with a soft edge a point wide
+            mask[i] = 1.0 - (d - HALF_WIDTH + 0.5).clamp(0.0, 1.0);
+        }
+        Some(mask)
+    }
+}
+
+/// handle the road console commands, roads are planned in the background and added by finish
+pub fn commands(
+    mut commands: EventReader<Command>,
+    world: Res<WorldSeed>,
+    mut roads: ResMut<Roads>,
+    mut planning: ResMut<Planning>,
+    mut changed: EventWriter<RoadChanged>,
+) {
+    for c in commands.iter() {
+        match c {
+            Command::Road(waypoints) => {
+                let (world, waypoints) = (world.0, waypoints.clone());
+                let task = AsyncComputeTaskPool::get().spawn(async move { plan(world, &waypoints) });
+                planning.tasks.push((world, task));
+                println!("planning road");
+            }
+            Command::ClearRoads => {
+                changed.send_batch(roads.list.iter().map(RoadChanged::of));
+                roads.list.clear();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// add roads that have finished planning, roads planned for another world are dropped
+pub fn finish(
+    world: Res<WorldSeed>,
+    mut roads: ResMut<Roads>,
+    mut planning: ResMut<Planning>,
+    mut changed: EventWriter<RoadChanged>,
+) {
+    planning.tasks.retain_mut(|(w, task)| {
+        let result = match future::block_on(future::poll_once(task)) {
+            Some(r) => r,
+            None => return true,
+        };
+        match result {
+            Ok(r) if *w == world.0 => {
+                println!("road with {} points", r.points.len());
+                changed.send(RoadChanged::of(&r));
+                roads.list.push(r);
+            }
+            Ok(_) => (),
+            Err(e) => println!("{}", e),
+        }
+        false
+    });
+}
+
+/// roads follow the hights of one world, so they go when it changes
+/// every chunk is generated again for the new world anyway
+pub fn clear_on_world_change(
+    world: Res<WorldSeed>,
+    mut roads: ResMut<Roads>,
+) {
+    if world.is_changed() && !world.is_added() && !roads.list.is_empty() {
+        roads.list.clear();
+    }
+}
+
+#[test]
+fn roads_are_graded_the_same_in_neighbouring_chunks() {
+    let mut roads = Roads::default();
+    // along the edge between chunk (0, 0) and (0, -1)
+    let r = roads.connect(0, &[Vec2::new(2.0, 0.0), Vec2::new(20.0, 0.0)]).unwrap();
+    assert!(r.points.len() > 2);
+    let mut a = map::genhightmap((0.0, 0.0), 0);
+    let mut b = map::genhightmap((0.0, -1.0), 0);
+    let mask = roads.grade(&mut a, (0.0, 0.0)).unwrap();
+    roads.grade(&mut b, (0.0, -1.0)).unwrap();
+    for x in 0..CHUNK_SIZE {
+        // the first row of a is the last row of b
+        assert_eq!(a[x], b[x + (CHUNK_SIZE - 1) * CHUNK_SIZE]);
+    }
+    assert!(mask[20] > 0.9);
+    assert!(roads.grade(&mut map::genhightmap((5.0, 5.0), 0), (5.0, 5.0)).is_none());
+    // both chunks along the edge, and no further than the road reaches
+    assert_eq!(roads.list[0].chunks(), ((0, -1), (0, 0)));
+}
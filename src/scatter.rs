@@ -0,0 +1,302 @@
+//! trees, bushes, grass and rocks scattered over each chunk
+//! placement only depends on the chunk seed, so a chunk always gets the same plants however it is loaded
+
+use bevy::prelude::*;
+use bevy::render::mesh::{shape, Indices, Mesh};
+use bevy::render::render_resource::PrimitiveTopology;
+use std::convert::TryInto;
+use crate::chunk::{Map, WorldSeed};
+use crate::map::{self, ChunkData, CHUNK_SIZE, VOXEL_SCALE};
+
+/// minimum distance between scattered things, in points
+const SPACING: f32 = 3.5;
+/// how many tries bridson's algorithm makes arround each point before giving up on it
+const ATTEMPTS: usize = 20;
+/// no trees above this hight (hightmap units)
+const TREE_LINE: f32 = 15.0;
+/// no trees where genslope is over this
+const TREE_SLOPE: f32 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Tree,
+    Bush,
+    Grass,
+    Rock,
+}
+
+/// one scattered thing, position is in chunk local world units like the chunk mesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub kind: Kind,
+    pub position: Vec3,
+    /// rotation arround y, radians
+    pub yaw: f32,
+    pub scale: f32,
+}
+
+/// a tiny deterministic random number generator built on map::hash
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_add(0x6d2b79f5);
+        map::hash(self.0)
+    }
+
+    /// uniform in [0, 1)
+    fn f32(&mut self) -> f32 {
+        (self.next() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// the rng for a chunk, from the chunk seed after world_chunk
+fn chunk_rng(seed: (f32, f32)) -> Rng {
+    Rng(map::hash(map::hash(seed.0 as i32 as u32) ^ seed.1 as i32 as u32))
+}
+
+/// poisson disk samples in [0, size) on both axes, no two closer than radius (bridson's algorithm)
+fn poisson_disk(rng: &mut Rng, size: f32, radius: f32) -> Vec<Vec2> {
+    let cell = radius / std::f32::consts::SQRT_2;
+    let cells = (size / cell).ceil() as usize;
+    // index into points for each grid cell, at most one point fits in a cell
+    let mut grid: Vec<Option<usize>> = vec![None; cells * cells];
+    let cell_of = |p: Vec2| ((p.x / cell) as usize, (p.y / cell) as usize);
+
+    let first = Vec2::new(rng.f32() * size, rng.f32() * size);
+    let mut points = vec![first];
+    let mut active = vec![0];
+    let (cx, cy) = cell_of(first);
+    grid[cx + cy * cells] = Some(0);
+
+    while !active.is_empty() {
+        let a = (rng.next() as usize) % active.len();
+        let centre = points[active[a]];
+        let mut found = false;
+        for _ in 0..ATTEMPTS {
+            // a random point in the ring between radius and 2 * radius
+            let angle = rng.f32() * std::f32::consts::TAU;
+            let dist = radius * (1.0 + rng.f32());
+            let p = centre + Vec2::new(angle.cos(), angle.sin()) * dist;
+            if p.x < 0.0 || p.y < 0.0 || p.x >= size || p.y >= size {
+                continue;
+            }
+            let (px, py) = cell_of(p);
+            let near = (px.saturating_sub(2)..(px + 3).min(cells))
+                .flat_map(|x| (py.saturating_sub(2)..(py + 3).min(cells)).map(move |y| (x, y)))
+                .filter_map(|(x, y)| grid[x + y * cells])
+                .any(|i| points[i].distance(p) < radius);
+            if !near {
+                grid[px + py * cells] = Some(points.len());
+                active.push(points.len());
+                points.push(p);
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            active.swap_remove(a);
+        }
+    }
+    points
+}
+
+/// what grows at a point, roll is a random number in [0, 1) that picks between the allowed kinds
+/// h is in hightmap units and slope is from genslope
+pub fn pick(h: f32, slope: f32, roll: f32) -> Option<Kind> {
+    let weights: &[(Kind, f32)] = if h < map::SAND_HEIGHT {
+        // under water
+        &[]
+    } else if slope > map::STONE_SLOPE {
+        &[(Kind::Rock, 0.3)]
+    } else if h > map::SNOW_HEIGHT {
+        &[(Kind::Rock, 0.15)]
+    } else if h < map::GRASS_HEIGHT {
+        // beach
+        &[(Kind::Rock, 0.1)]
+    } else if slope < TREE_SLOPE && h < TREE_LINE {
+        &[(Kind::Tree, 0.35), (Kind::Bush, 0.2), (Kind::Grass, 0.3), (Kind::Rock, 0.05)]
+    } else {
+        &[(Kind::Bush, 0.3), (Kind::Grass, 0.4), (Kind::Rock, 0.1)]
+    };
+    let mut total = 0.0;
+    for (kind, w) in weights {
+        total += w;
+        if roll < total {
+            return Some(*kind);
+        }
+    }
+    None
+}
+
+/// the hight between points, bilinear
+fn hight_at(hightmap: &ChunkData<f32>, p: Vec2) -> f32 {
+    let last = CHUNK_SIZE - 1;
+    let x = (p.x as usize).min(last - 1);
+    let z = (p.y as usize).min(last - 1);
+    let fx = p.x - x as f32;
+    let fz = p.y - z as f32;
+    let h = |x: usize, z: usize| hightmap[x + z * CHUNK_SIZE];
+    let a = h(x, z) + (h(x + 1, z) - h(x, z)) * fx;
+    let b = h(x, z + 1) + (h(x + 1, z + 1) - h(x, z + 1)) * fx;
+    a + (b - a) * fz
+}
+
+/// everything scattered over a chunk, seed is the chunk seed after world_chunk
+/// nothing is put on roads
+pub fn scatter(hightmap: &ChunkData<f32>, roads: Option<&ChunkData<f32>>, seed: (f32, f32)) -> Vec<Instance> {
+    let slope = map::genslope(hightmap);
+    let mut rng = chunk_rng(seed);
+    poisson_disk(&mut rng, (CHUNK_SIZE - 1) as f32, SPACING)
+        .into_iter()
+        .filter_map(|p| {
+            // draw every number even for empty points, so changing the rules for one kind does not move the rest
+            let roll = rng.f32();
+            let yaw = rng.f32() * std::f32::consts::TAU;
+            let scale = 0.7 + rng.f32() * 0.6;
+            let nearest = p.x.round() as usize + p.y.round() as usize * CHUNK_SIZE;
+            if roads.is_some_and(|r| r[nearest] > 0.0) {
+                return None;
+            }
+            let s = slope[nearest];
+            let h = hight_at(hightmap, p);
+            pick(h, s, roll).map(|kind| Instance {
+                kind,
+                position: Vec3::new(p.x, h, p.y) * VOXEL_SCALE,
+                yaw,
+                scale,
+            })
+        })
+        .collect()
+}
+
+/// copies of a mesh at each transform as one mesh, so a chunk has an entity per kind instead of one per instance
+/// the transforms are scaled evenly, so normals only need rotating
+pub fn merge(mesh: &Mesh, transforms: &[Transform]) -> Mesh {
+    let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION).and_then(|p| p.as_float3()).unwrap_or(&[]);
+    let normals = mesh.attribute(Mesh::ATTRIBUTE_NORMAL).and_then(|n| n.as_float3()).unwrap_or(&[]);
+    let indices: Vec<u32> = match mesh.indices() {
+        Some(i) => i.iter().map(|i| i as u32).collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    let (mut p, mut n, mut idx) = (Vec::new(), Vec::new(), Vec::new());
+    for t in transforms {
+        let base = p.len() as u32;
+        let m = t.compute_matrix();
+        p.extend(positions.iter().map(|v| m.transform_point3(Vec3::from(*v)).to_array()));
+        n.extend(normals.iter().map(|v| (t.rotation * Vec3::from(*v)).to_array()));
+        idx.extend(indices.iter().map(|i| base + i));
+    }
+    let mut out = Mesh::new(PrimitiveTopology::TriangleList);
+    out.insert_attribute(Mesh::ATTRIBUTE_POSITION, p);
+    out.insert_attribute(Mesh::ATTRIBUTE_NORMAL, n);
+    out.set_indices(Some(Indices::U32(idx)));
+    out
+}
+
+/// the mesh and material drawn for a kind
+struct Placeholder {
+    kind: Kind,
+    /// kept on the cpu, it is copied into each chunk's merged mesh
+    mesh: Mesh,
+    material: Handle<StandardMaterial>,
+    /// how far the mesh origin is above the ground
+    offset: Vec3,
+}
+
+/// placeholder meshes and materials, shared by every chunk
+#[derive(Resource)]
+pub struct ScatterAssets {
+    placeholders: Vec<Placeholder>,
+}
+
+/// marks a chunk render entity that has had things scattered on it
+#[derive(Component)]
+pub struct Scattered;
+
+/// make the placeholder meshes
+pub fn init(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut placeholder = |kind, mesh, (r, g, b), offset| Placeholder {
+        kind,
+        mesh,
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(r, g, b),
+            perceptual_roughness: 0.9,
+            ..Default::default()
+        }),
+        offset: Vec3::Y * offset,
+    };
+    let placeholders = vec![
+        placeholder(Kind::Tree, shape::Capsule {radius: 0.25, depth: 1.2, ..Default::default()}.into(), (0.1, 0.3, 0.1), 0.75),
+        placeholder(Kind::Bush, shape::Icosphere {radius: 0.3, subdivisions: 1}.try_into().unwrap(), (0.2, 0.45, 0.15), 0.15),
+        placeholder(Kind::Grass, shape::Cylinder {radius: 0.2, height: 0.15, resolution: 6, segments: 1}.into(), (0.35, 0.6, 0.2), 0.05),
+        // sunk a bit, so rocks on slopes do not float
+        placeholder(Kind::Rock, shape::Icosphere {radius: 0.25, subdivisions: 0}.try_into().unwrap(), (0.45, 0.43, 0.4), -0.05),
+    ];
+    commands.insert_resource(ScatterAssets {placeholders});
+}
+
+/// scatter things over newly generated chunks, as children of the render entity so they unload with it
+/// each kind is merged into one mesh per chunk, so denser scatter does not mean more entities
+pub fn spawn(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    assets: Res<ScatterAssets>,
+    world: Res<WorldSeed>,
+    maps: Query<&Map>,
+    done: Query<(), With<Scattered>>,
+) {
+    for map in maps.iter() {
+        let (render, hightmap) = match (map.render, &map.hightmap) {
+            (Some(r), Some(h)) => (r, h),
+            _ => continue
+        };
+        if done.get(render).is_ok() {
+            continue;
+        }
+        let instances = scatter(hightmap, map.roads.as_ref(), map::world_chunk(map.seed, world.0));
+        // nothing floating over cave mouths
+        let grounded = |i: &Instance| {
+            let (x, z) = ((i.position.x / VOXEL_SCALE).round() as usize, (i.position.z / VOXEL_SCALE).round() as usize);
+            map.volume.is_none() || map.surfaces(x, z).first().is_some_and(|h| (h * VOXEL_SCALE - i.position.y).abs() <= 0.5)
+        };
+        commands.entity(render).insert(Scattered).with_children(|c| {
+            for p in assets.placeholders.iter() {
+                let transforms: Vec<Transform> = instances.iter()
+                    .filter(|i| i.kind == p.kind && grounded(i))
+                    .map(|i| Transform::from_translation(i.position + p.offset * i.scale)
+                        .with_rotation(Quat::from_rotation_y(i.yaw))
+                        .with_scale(Vec3::splat(i.scale)))
+                    .collect();
+                if transforms.is_empty() {
+                    continue;
+                }
+                c.spawn(PbrBundle {
+                    mesh: meshes.add(merge(&p.mesh, &transforms)),
+                    material: p.material.clone(),
+                    ..Default::default()
+                });
+            }
+        });
+    }
+}
+
+#[test]
+fn scatter_is_deterministic() {
+    let hightmap = Box::new([1.0_f32; map::CHUNK_SQSIZE]);
+    let a = scatter(&hightmap, None, (3.0, -7.0));
+    assert!(!a.is_empty());
+    assert_eq!(a, scatter(&hightmap, None, (3.0, -7.0)));
+    assert_ne!(a, scatter(&hightmap, None, (4.0, -7.0)));
+    // nothing under water or trees on cliffs
+    assert!(scatter(&Box::new([-2.0_f32; map::CHUNK_SQSIZE]), None, (3.0, -7.0)).is_empty());
+    assert!((0..100).all(|i| pick(1.0, 2.0, i as f32 / 100.0) != Some(Kind::Tree)));
+    // one mesh with a copy per instance
+    let rock: Mesh = shape::Icosphere {radius: 0.25, subdivisions: 0}.try_into().unwrap();
+    let merged = merge(&rock, &[Transform::IDENTITY, Transform::from_xyz(5.0, 0.0, 0.0)]);
+    assert_eq!(merged.count_vertices(), rock.count_vertices() * 2);
+    assert_eq!(merged.indices().unwrap().len(), rock.indices().unwrap().len() * 2);
+}
@@ -0,0 +1,215 @@
+//! a small http server that generates terrain on request, for tools that want chunks without running the generator themselves
+//! `GET /chunk/{x}/{z}/height.bin` is the hights of a chunk as little endian f32, row by row
+//! `GET /tile/{z}/{x}/{y}.png` is a tile of the same pyramid terrain-gen writes with `--outputs tiles`
+//! `GET /mesh/{x}/{z}.glb` is the chunk mesh as binary gltf with its texture
+//! responses are kept in an lru cache, so asking again is cheap.
+//! requests are answered by a fixed number of worker threads, connections wait in a bounded queue for them
+
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::sync_channel;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::export::{self, Chunk, Tiles};
+use crate::heightmap::Heightmap;
+use crate::lru::Lru;
+use crate::poi::Pois;
+use crate::pyramid::{self, Pyramid};
+
+/// a response body and its content type
+type Body = (&'static str, Arc<Vec<u8>>);
+
+/// the generated chunks kept, so /chunk, /mesh and /tile of the same chunk only generate it once
+const CHUNKS: usize = 64;
+/// connections waiting for a worker, per worker, before accepting more waits
+const QUEUED: usize = 4;
+/// how long a worker waits on a connection that is not sending or reading before giving up on it
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// the most of a request that is read, the request line and headers together
+const MAX_REQUEST: u64 = 16 * 1024;
+
+pub struct Server {
+    pub world: u32,
+    pub import: Option<Heightmap>,
+    pub tiles: Tiles,
+    /// the chunks covered by /tile
+    pub pyramid: Pyramid,
+    cache: Mutex<Lru<String, Body>>,
+    chunks: Mutex<Lru<(i32, i32), Arc<Chunk>>>,
+    /// shared so poi regions are only worked out once
+    pois: Mutex<Pois>,
+}
+
+impl Server {
+    pub fn new(world: u32, import: Option<Heightmap>, tiles: Tiles, pyramid: Pyramid, cache: usize) -> Server {
+        Server {world, import, tiles, pyramid, cache: Mutex::new(Lru::new(cache)), chunks: Mutex::new(Lru::new(CHUNKS)), pois: Mutex::new(Pois::default())}
+    }
+
+    fn chunk(&self, seed: (i32, i32)) -> Arc<Chunk> {
+        if let Some(chunk) = self.chunks.lock().unwrap().get(&seed) {
+            return chunk;
+        }
+        // generated without holding a lock, so other requests are not held up
+        let mut pois = self.pois.lock().unwrap().around(self.world, (seed.0 as f32, seed.1 as f32));
+        let chunk = Arc::new(Chunk::generate(self.world, seed, self.import.as_ref(), &mut pois));
+        self.chunks.lock().unwrap().insert(seed, chunk.clone());
+        chunk
+    }
+
+    /// the body for a path, None if there is nothing there
+    pub fn get(&self, path: &str) -> Result<Option<Body>, String> {
+        if let Some(body) = self.cache.lock().unwrap().get(path) {
+            return Ok(Some(body));
+        }
+        // generated without holding the lock, so other requests are not held up
+        let body = match self.generate(path)? {
+            Some((kind, bytes)) => (kind, Arc::new(bytes)),
+            None => return Ok(None),
+        };
+        self.cache.lock().unwrap().insert(path.to_string(), body.clone());
+        Ok(Some(body))
+    }
+
+    fn generate(&self, path: &str) -> Result<Option<(&'static str, Vec<u8>)>, String> {
+        let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let int = |s: &str| s.parse::<i32>().ok();
+        match parts[..] {
+            ["chunk", x, z, "height.bin"] => {
+                let (Some(x), Some(z)) = (int(x), int(z)) else { return Ok(None) };
+                let bytes = self.chunk((x, z)).hightmap.iter().flat_map(|v| v.to_le_bytes()).collect();
+                Ok(Some(("application/octet-stream", bytes)))
+            }
+            ["mesh", x, z] => {
+                let (Some(x), Some(z)) = (int(x), z.strip_suffix(".glb").and_then(int)) else { return Ok(None) };
+                let chunk = self.chunk((x, z));
+                let mut bytes = Vec::new();
+                export::write_glb(&mut bytes, &chunk.mesh(), Some(chunk.texture(self.world, &self.tiles)))?;
+                Ok(Some(("model/gltf-binary", bytes)))
+            }
+            ["tile", zoom, x, y] => {
+                let (Some(zoom), Some(x), Some(y)) = (int(zoom), int(x), y.strip_suffix(".png").and_then(int)) else { return Ok(None) };
+                if zoom < 0 || x < 0 || y < 0 {
+                    return Ok(None);
+                }
+                let tile = match self.tile(zoom as u32, (x as u32, y as u32))? {
+                    Some(t) => t,
+                    None => return Ok(None),
+                };
+                let mut png = Cursor::new(Vec::new());
+                tile.write_to(&mut png, image::ImageOutputFormat::Png).map_err(|e| e.to_string())?;
+                Ok(Some(("image/png", png.into_inner())))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// a tile of the pyramid, deeper zooms come from the cache when they are in it
+    fn tile(&self, zoom: u32, tile: (u32, u32)) -> Result<Option<image::RgbaImage>, String> {
+        let p = &self.pyramid;
+        // checked before anything is generated or looked up, tiles outside the pyramid are empty
+        if zoom > p.max_zoom || tile.0 >= p.tiles(zoom).0 || tile.1 >= p.tiles(zoom).1 {
+            return Ok(None);
+        }
+        if zoom == p.max_zoom {
+            let seed = (p.from.0 + tile.0 as i32, p.from.1 + tile.1 as i32);
+            let chunk = self.chunk(seed);
+            return pyramid::chunk_tile(chunk.texture(self.world, &self.tiles), &chunk.hightmap).map(Some);
+        }
+        let mut children = [None, None, None, None];
+        for (i, (dx, dy)) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter().enumerate() {
+            if let Some((_, png)) = self.get(&format!("/tile/{}/{}/{}.png", zoom + 1, tile.0 * 2 + dx, tile.1 * 2 + dy))? {
+                children[i] = Some(image::load_from_memory(&png).map_err(|e| e.to_string())?.into_rgba8());
+            }
+        }
+        if children.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        Ok(Some(pyramid::downsample(&children)))
+    }
+
+    /// answer one request, the connection is closed after it
+    fn handle(&self, stream: TcpStream) -> std::io::Result<()> {
+        // so an idle connection cant hold a worker for good
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(&stream).take(MAX_REQUEST);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // skip the headers
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+        let mut words = request.split_whitespace();
+        let (status, kind, body): (&str, &str, Arc<Vec<u8>>) = match (words.next(), words.next()) {
+            _ if reader.limit() == 0 => ("431 Request Header Fields Too Large", "text/plain", Arc::new(b"request too long".to_vec())),
+            (Some("GET"), Some(path)) => match self.get(path) {
+                Ok(Some((kind, body))) => ("200 OK", kind, body),
+                Ok(None) => ("404 Not Found", "text/plain", Arc::new(b"not found".to_vec())),
+                Err(e) => ("500 Internal Server Error", "text/plain", Arc::new(e.into_bytes())),
+            },
+            _ => ("405 Method Not Allowed", "text/plain", Arc::new(b"only GET is supported".to_vec())),
+        };
+        let mut out = &stream;
+        // the web tools are served from somewhere else
+        write!(out, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n", status, kind, body.len())?;
+        out.write_all(&body)?;
+        out.flush()
+    }
+
+    /// answer requests on workers threads until the listener fails
+    pub fn serve(self: Arc<Self>, listener: TcpListener, workers: usize) -> Result<(), String> {
+        let workers = workers.max(1);
+        let (tx, rx) = sync_channel::<TcpStream>(workers * QUEUED);
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..workers {
+            let (server, rx) = (self.clone(), rx.clone());
+            std::thread::spawn(move || loop {
+                // the lock is only held while waiting, not while answering
+                let stream = match rx.lock().unwrap().recv() {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+                if let Err(e) = server.handle(stream) {
+                    eprintln!("request failed: {}", e);
+                }
+            });
+        }
+        for stream in listener.incoming() {
+            let stream = stream.map_err(|e| e.to_string())?;
+            // blocks while the queue is full, so a flood of connections waits in the listen backlog
+            tx.send(stream).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn server_answers_and_caches() {
+    let tiles = Tiles::load(std::path::Path::new("assets")).unwrap();
+    let server = Arc::new(Server::new(0, None, tiles, Pyramid::new((0, 0), (1, 1)), 16));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || server.serve(listener, 2));
+    let fetch = move |path: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (String::from_utf8_lossy(&response[..split]).to_string(), response[split + 4..].to_vec())
+    };
+    let (head, body) = fetch("/chunk/0/0/height.bin");
+    assert!(head.starts_with("HTTP/1.1 200"));
+    let hights: Vec<f32> = body.chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    assert_eq!(hights[..], Chunk::generate(0, (0, 0), None, &mut Pois::default()).hightmap[..]);
+    assert!(fetch("/tile/0/0/0.png").1.starts_with(b"\x89PNG"));
+    assert!(fetch("/tile/1/2/0.png").0.starts_with("HTTP/1.1 404"));
+    // tiles outside the pyramid are not looked for, however far out
+    assert!(fetch("/tile/0/5/5.png").0.starts_with("HTTP/1.1 404"));
+    assert!(fetch("/tile/1/2147483647/0.png").0.starts_with("HTTP/1.1 404"));
+    assert!(fetch("/mesh/0/0.glb").1.starts_with(b"glTF"));
+    // more connections at once than there are workers all get answered
+    let answers: Vec<_> = (0..8).map(|x| std::thread::spawn(move || fetch(&format!("/chunk/{}/0/height.bin", x % 2)).0)).collect();
+    assert!(answers.into_iter().all(|a| a.join().unwrap().starts_with("HTTP/1.1 200")));
+}
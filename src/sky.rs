@@ -0,0 +1,166 @@
+//! the sky and distance fog, so the edge of the loaded world fades out instead of stopping
+
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::mesh::{shape, Mesh};
+use crate::console::Command;
+use crate::loader::ViewDistance;
+
+/// the sky is drawn on a sphere this big arround the camera, it has to be inside the far plane
+/// it is scaled up when more is loaded, see dome_radius
+const SKY_RADIUS: f32 = 500.0;
+
+/// fog starts at this fraction of the view distance
+const FOG_START: f32 = 0.4;
+
+/// the colours of the sky, and how distant terrain fades into it
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Sky {
+    /// the sky at the horizon, also the fog colour
+    pub horizon: Color,
+    /// the sky straight up
+    pub zenith: Color,
+    /// the colour of sunlight scattered by the fog, looking towards the sun
+    pub sun_glow: Color,
+    /// fade distant terrain with blue haze (exponential fog) instead of linear fog
+    pub aerial: bool,
+}
+
+impl Default for Sky {
+    fn default() -> Sky {
+        Sky {
+            horizon: Color::rgb(0.7, 0.8, 0.9),
+            zenith: Color::rgb(0.25, 0.45, 0.8),
+            sun_glow: Color::rgba(1.0, 0.95, 0.8, 0.5),
+            aerial: true,
+        }
+    }
+}
+
+/// the sphere the sky is drawn on
+#[derive(Component)]
+pub struct SkyDome;
+
+/// spawn the sky dome, it is coloured by apply
+pub fn init(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(Sky::default());
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(shape::UVSphere {
+                radius: SKY_RADIUS,
+                sectors: 32,
+                stacks: 16,
+            }.into()),
+            material: materials.add(StandardMaterial {
+                unlit: true,
+                fog_enabled: false,
+                // seen from inside
+                cull_mode: None,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        NotShadowCaster,
+        SkyDome,
+    ));
+}
+
+/// keep the sky centered on the camera, so it is never reached
+pub fn follow(
+    cameras: Query<&Transform, (With<Camera>, Without<SkyDome>)>,
+    mut domes: Query<&mut Transform, With<SkyDome>>,
+) {
+    if let Some(c) = cameras.iter().next() {
+        for mut d in domes.iter_mut() {
+            d.translation = c.translation;
+        }
+    }
+}
+
+/// how big the dome has to be to hold every loaded chunk, chunks stay loaded up to a chunk past the view distance
+pub fn dome_radius(view: &ViewDistance) -> f32 {
+    let furthest = (view.radius() + 2.0 * crate::map::getchunksize()) * std::f32::consts::SQRT_2;
+    furthest.max(SKY_RADIUS)
+}
+
+/// recolour the sky and fog when the sky or the view distance changes
+/// the dome grows with the view distance, and the far plane with it
+pub fn apply(
+    sky: Res<Sky>,
+    view: Res<ViewDistance>,
+    mut clear: ResMut<ClearColor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut domes: Query<(&Handle<Mesh>, &mut Transform), With<SkyDome>>,
+    mut fogs: Query<&mut FogSettings>,
+    mut projections: Query<&mut Projection, With<Camera>>,
+) {
+    if !sky.is_changed() && !view.is_changed() {
+        return;
+    }
+    clear.0 = sky.horizon;
+    let radius = dome_radius(&view);
+    for (h, mut t) in domes.iter_mut() {
+        t.scale = Vec3::splat(radius / SKY_RADIUS);
+        if let Some(mesh) = meshes.get_mut(h) {
+            paint(mesh, &sky);
+        }
+    }
+    for mut p in projections.iter_mut() {
+        if let Projection::Perspective(p) = p.as_mut() {
+            p.far = p.far.max(radius * 2.0);
+        }
+    }
+    for mut fog in fogs.iter_mut() {
+        *fog = fog_settings(&sky, view.radius());
+    }
+}
+
+/// fog that hides everything past the loaded radius
+pub fn fog_settings(sky: &Sky, radius: f32) -> FogSettings {
+    let falloff = if sky.aerial {
+        // everything is at 5% contrast at the radius, and distant terrain takes on the sky colour
+        FogFalloff::from_visibility_colors(radius, Color::rgb(0.35, 0.5, 0.66), sky.horizon)
+    } else {
+        FogFalloff::Linear {
+            start: radius * FOG_START,
+            end: radius,
+        }
+    };
+    FogSettings {
+        color: sky.horizon,
+        directional_light_color: if sky.aerial {sky.sun_glow} else {Color::NONE},
+        directional_light_exponent: 30.0,
+        falloff,
+    }
+}
+
+/// colour the dome with vertex colours, from the horizon colour at and below the horizon up to the zenith colour
+fn paint(mesh: &mut Mesh, sky: &Sky) {
+    let colours: Vec<[f32; 4]> = match mesh.attribute(Mesh::ATTRIBUTE_POSITION).and_then(|p| p.as_float3()) {
+        Some(p) => p.iter().map(|v| {
+            let t = (v[1] / SKY_RADIUS).max(0.0).sqrt();
+            let h = sky.horizon.as_linear_rgba_f32();
+            let z = sky.zenith.as_linear_rgba_f32();
+            [h[0] + (z[0] - h[0]) * t, h[1] + (z[1] - h[1]) * t, h[2] + (z[2] - h[2]) * t, 1.0]
+        }).collect(),
+        None => return
+    };
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colours);
+}
+
+/// handle the aerial console command
+pub fn commands(
+    mut commands: EventReader<Command>,
+    mut sky: ResMut<Sky>,
+) {
+    for c in commands.iter() {
+        if *c == Command::ToggleAerial {
+            sky.aerial = !sky.aerial;
+            println!("aerial perspective {}", if sky.aerial {"on"} else {"off"});
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! the time of day, moving the sun and moon and colouring the light and sky to match
+
+use bevy::pbr::CascadeShadowConfigBuilder;
+use bevy::prelude::*;
+use std::f32::consts::PI;
+use crate::console::Command;
+use crate::input::{Action, ActionState};
+use crate::loader::ViewDistance;
+use crate::sky::Sky;
+
+/// sun illuminance at noon, in lux
+const NOON_ILLUMINANCE: f32 = 30000.0;
+/// moon illuminance when it is high, much brighter than a real moon so the night is not black
+const MOON_ILLUMINANCE: f32 = 1500.0;
+/// tilt of the suns path away from straight overhead, in radians
+const SUN_TILT: f32 = 0.4;
+/// how fast the time changes while scrubbing, in hours per second
+const SCRUB_SPEED: f32 = 3.0;
+
+/// the time of day in hours, 6 is sunrise and 18 is sunset
+#[derive(Resource, Debug, Clone)]
+pub struct TimeOfDay {
+    pub hours: f32,
+    /// hours per real second
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> TimeOfDay {
+        TimeOfDay {
+            hours: 10.0,
+            // a day takes 10 minutes
+            speed: 24.0 / 600.0,
+            paused: false,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// the direction towards the sun, it rises on +x and sets on -x
+    pub fn to_sun(&self) -> Vec3 {
+        let a = (self.hours - 6.0) / 12.0 * PI;
+        Quat::from_rotation_x(SUN_TILT) * Vec3::new(a.cos(), a.sin(), 0.0)
+    }
+}
+
+#[derive(Component)]
+pub struct Sun;
+
+#[derive(Component)]
+pub struct Moon;
+
+/// spawn the sun and moon, update moves them into place
+pub fn init(mut commands: Commands) {
+    commands.insert_resource(TimeOfDay::default());
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: NOON_ILLUMINANCE,
+                shadows_enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        Sun,
+    ));
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                color: Color::rgb(0.6, 0.7, 1.0),
+                illuminance: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        Moon,
+    ));
+}
+
+/// pause or scrub time with hotkeys, and advance it
+pub fn tick(
+    time: Res<Time>,
+    state: Res<ActionState>,
+    mut day: ResMut<TimeOfDay>,
+) {
+    if state.just_pressed(Action::PauseTime) {
+        day.paused = !day.paused;
+        println!("time {}", if day.paused {"paused"} else {"running"});
+    }
+    let scrub = state.axis(Action::TimeForward, Action::TimeBack);
+    let step = if scrub != 0.0 {
+        scrub * SCRUB_SPEED
+    } else if day.paused {
+        return;
+    } else {
+        day.speed
+    };
+    day.hours = (day.hours + step * time.delta_seconds()).rem_euclid(24.0);
+}
+
+/// handle the time console command
+pub fn commands(
+    mut commands: EventReader<Command>,
+    mut day: ResMut<TimeOfDay>,
+) {
+    for c in commands.iter() {
+        if let Command::Time(h) = c {
+            day.hours = h.rem_euclid(24.0);
+        }
+    }
+}
+
+/// move and colour the sun, moon, ambient light and sky for the time of day
+pub fn update(
+    day: Res<TimeOfDay>,
+    mut ambient: ResMut<AmbientLight>,
+    mut sky: ResMut<Sky>,
+    // the only directional lights are the sun and moon
+    mut lights: Query<(&mut Transform, &mut DirectionalLight, Option<&Moon>)>,
+) {
+    if !day.is_changed() {
+        return;
+    }
+    let to_sun = day.to_sun();
+    let e = to_sun.y;
+    // 0 at night, 1 in full day
+    let daylight = smoothstep(-0.1, 0.25, e);
+    // strongest with the sun on the horizon
+    let low_sun = (1.0 - e.abs() / 0.25).max(0.0);
+
+    for (mut t, mut light, moon) in lights.iter_mut() {
+        if moon.is_some() {
+            *t = Transform::from_translation(-to_sun).looking_at(Vec3::ZERO, Vec3::Y);
+            light.illuminance = MOON_ILLUMINANCE * smoothstep(-0.05, 0.3, -e);
+        } else {
+            *t = Transform::from_translation(to_sun).looking_at(Vec3::ZERO, Vec3::Y);
+            light.illuminance = NOON_ILLUMINANCE * smoothstep(-0.05, 0.3, e);
+            // warm near the horizon, white when high
+            light.color = mix(Color::rgb(1.0, 0.55, 0.3), Color::rgb(1.0, 0.98, 0.95), smoothstep(0.0, 0.4, e));
+        }
+    }
+
+    ambient.color = mix(Color::rgb(0.3, 0.35, 0.6), Color::rgb(0.8, 0.85, 1.0), daylight);
+    ambient.brightness = 0.03 + 0.07 * daylight;
+
+    let horizon = mix(Color::rgb(0.04, 0.05, 0.1), Color::rgb(0.7, 0.8, 0.9), daylight);
+    // the sky is only touched when it changes, a changed sky repaints the dome and resets the fog
+    sky.set_if_neq(Sky {
+        horizon: mix(horizon, Color::rgb(0.9, 0.55, 0.35), low_sun * 0.6),
+        zenith: mix(Color::rgb(0.01, 0.01, 0.04), Color::rgb(0.25, 0.45, 0.8), daylight),
+        ..sky.clone()
+    });
+}
+
+/// fit the shadow cascades to the loaded terrain
+pub fn shadows(
+    mut commands: Commands,
+    view: Res<ViewDistance>,
+    suns: Query<Entity, With<Sun>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+    for e in suns.iter() {
+        commands.entity(e).insert(CascadeShadowConfigBuilder {
+            num_cascades: 4,
+            minimum_distance: 0.1,
+            maximum_distance: view.radius(),
+            first_cascade_far_bound: view.radius() / 8.0,
+            ..Default::default()
+        }.build());
+    }
+}
+
+fn smoothstep(a: f32, b: f32, x: f32) -> f32 {
+    let t = ((x - a) / (b - a)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// crate::mix for colours
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    let [r, g, b, a] = crate::mix(a.as_rgba_f32(), b.as_rgba_f32(), t);
+    Color::rgba(r, g, b, a)
+}
+
+#[test]
+fn sun_rises_and_sets() {
+    let at = |hours| TimeOfDay {hours, ..Default::default()}.to_sun();
+    assert!(at(6.0).y.abs() < 0.01);
+    assert!(at(12.0).y > 0.9);
+    assert!(at(18.0).y.abs() < 0.01);
+    assert!(at(0.0).y < -0.9);
+}
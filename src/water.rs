@@ -0,0 +1,194 @@
+//! water surfaces, a transparent plane per chunk wherever the terrain is below the water level
+
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::mesh::{Indices, Mesh};
+use bevy::render::render_resource::{
+    AddressMode, AsBindGroup, Extent3d, FilterMode, PrimitiveTopology, SamplerDescriptor, ShaderRef,
+    TextureDimension, TextureFormat,
+};
+use bevy::render::texture::ImageSampler;
+use crate::map::{ChunkData, CHUNK_SIZE, VOXEL_SCALE};
+
+/// the water shader, relative to the assets folder
+pub const WATER_SHADER: &str = "shaders/water.wgsl";
+
+/// side length of the generated wave normal map
+const WAVE_TEXTURE_SIZE: usize = 64;
+
+pub use params::WaterParams;
+
+// rustc counts the derive's per field checks as unused functions
+#[allow(dead_code)]
+mod params {
+    use bevy::prelude::Vec4;
+    use bevy::render::render_resource::ShaderType;
+
+    /// colours and wave settings, depths are in hightmap units (before VOXEL_SCALE)
+    #[derive(ShaderType, Debug, Clone, Copy)]
+    pub struct WaterParams {
+        pub shallow_color: Vec4,
+        pub deep_color: Vec4,
+        pub foam_color: Vec4,
+        /// depth at which the water is fully deep_color
+        pub deep: f32,
+        /// foam fades out by this depth
+        pub foam_depth: f32,
+        /// side length of a tile of the wave normal map in world units
+        pub wave_scale: f32,
+        /// how fast the waves scroll, in tiles per second
+        pub wave_speed: f32,
+    }
+}
+
+impl Default for WaterParams {
+    fn default() -> WaterParams {
+        WaterParams {
+            shallow_color: Vec4::new(0.1, 0.45, 0.5, 0.45),
+            deep_color: Vec4::new(0.02, 0.1, 0.25, 0.9),
+            foam_color: Vec4::new(0.9, 0.95, 1.0, 0.9),
+            deep: 3.0,
+            foam_depth: 0.25,
+            wave_scale: 6.0,
+            wave_speed: 0.03,
+        }
+    }
+}
+
+/// a material for water meshes, all chunks share one
+/// the depth of the terrain under the water is passed in the x uv coordinate
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "b3a1e6c9-2d47-4f0e-8c15-7e9d4a6f3b28"]
+pub struct WaterMaterial {
+    #[uniform(0)]
+    pub params: WaterParams,
+    /// tiling wave normals, y up
+    #[texture(1)]
+    #[sampler(2)]
+    pub waves: Handle<Image>,
+}
+
+impl Material for WaterMaterial {
+    fn fragment_shader() -> ShaderRef {
+        WATER_SHADER.into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
+
+/// the shared water material
+#[derive(Resource)]
+pub struct WaterAssets {
+    pub material: Handle<WaterMaterial>,
+}
+
+/// build the wave texture and the water material
+pub fn init(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<WaterMaterial>>,
+) {
+    let waves = images.add(wave_normals());
+    commands.insert_resource(WaterAssets {
+        material: materials.add(WaterMaterial {
+            params: WaterParams::default(),
+            waves,
+        }),
+    });
+}
+
+/// a tiling normal map of a few sine waves, every wave fits a whole number of times so the edges match
+fn wave_normals() -> Image {
+    // (frequency x, frequency z, amplitude)
+    const WAVES: [(f32, f32, f32); 4] = [(1.0, 2.0, 0.6), (3.0, -1.0, 0.3), (-2.0, 5.0, 0.15), (7.0, 4.0, 0.06)];
+    let tau = std::f32::consts::TAU;
+    let data: Vec<u8> = (0..WAVE_TEXTURE_SIZE * WAVE_TEXTURE_SIZE).flat_map(|i| {
+        let u = (i % WAVE_TEXTURE_SIZE) as f32 / WAVE_TEXTURE_SIZE as f32;
+        let v = (i / WAVE_TEXTURE_SIZE) as f32 / WAVE_TEXTURE_SIZE as f32;
+        // the slope of the waves
+        let (mut dx, mut dz) = (0.0, 0.0);
+        for (fx, fz, a) in WAVES {
+            let c = (tau * (fx * u + fz * v)).cos() * a;
+            dx += c * fx;
+            dz += c * fz;
+        }
+        let n = Vec3::new(-dx, 8.0, -dz).normalize() * 0.5 + 0.5;
+        [(n.x * 255.0) as u8, (n.y * 255.0) as u8, (n.z * 255.0) as u8, 255]
+    }).collect();
+    let mut image = Image::new(
+        Extent3d {
+            width: WAVE_TEXTURE_SIZE as u32,
+            height: WAVE_TEXTURE_SIZE as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+    );
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..Default::default()
+    });
+    image
+}
+
+/// a flat mesh at level covering every quad of the chunk with a point at or under it, None if the chunk is dry
+/// level is in hightmap units, like the hightmap
+/// seabed is the ground under the water where the hightmap is clipped to level, see map::genseabed
+pub fn water_mesh(hightmap: &ChunkData<f32>, seabed: Option<&ChunkData<f32>>, level: f32) -> Option<Mesh> {
+    let wet = |x: usize, z: usize| hightmap[x + z * CHUNK_SIZE] <= level;
+    let mut indeces: Vec<u32> = Vec::new();
+    for z in 0..CHUNK_SIZE - 1 {
+        for x in 0..CHUNK_SIZE - 1 {
+            if wet(x, z) || wet(x + 1, z) || wet(x, z + 1) || wet(x + 1, z + 1) {
+                let i = (x + z * CHUNK_SIZE) as u32;
+                let s = CHUNK_SIZE as u32;
+                // same winding as chunktomesh
+                indeces.extend([i, i + s, i + 1, i + 1, i + s, i + s + 1]);
+            }
+        }
+    }
+    if indeces.is_empty() {
+        return None;
+    }
+    let position: Vec<[f32; 3]> = (0..hightmap.len())
+        .map(|i| [(i % CHUNK_SIZE) as f32 * VOXEL_SCALE, level * VOXEL_SCALE, (i / CHUNK_SIZE) as f32 * VOXEL_SCALE])
+        .collect();
+    let normals = vec![[0.0, 1.0, 0.0]; position.len()];
+    // negative on land, the shader uses that for the shoreline
+    let depth: Vec<[f32; 2]> = hightmap.iter().enumerate().map(|(i, h)| match seabed {
+        Some(s) if *h <= level => [level - s[i].min(*h), 0.0],
+        _ => [level - h, 0.0],
+    }).collect();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, depth);
+    mesh.set_indices(Some(Indices::U32(indeces)));
+    Some(mesh)
+}
+
+#[test]
+fn water_only_over_low_ground() {
+    let mut hightmap = Box::new([1.0_f32; crate::map::CHUNK_SQSIZE]);
+    assert!(water_mesh(&hightmap, None, crate::map::SEA_LEVEL).is_none());
+    // clipped to sea level, with the sea bed under it
+    hightmap[0] = crate::map::SEA_LEVEL;
+    let mut seabed = hightmap.clone();
+    seabed[0] = -2.0;
+    let mesh = water_mesh(&hightmap, Some(&seabed), crate::map::SEA_LEVEL).unwrap();
+    // only the corner quad
+    assert_eq!(mesh.indices().unwrap().len(), 6);
+    match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(bevy::render::mesh::VertexAttributeValues::Float32x2(depth)) => {
+            assert!((depth[0][0] - (crate::map::SEA_LEVEL + 2.0)).abs() < 1e-5);
+            assert!(depth[1][0] < 0.0);
+        }
+        _ => panic!("no depth"),
+    }
+}